@@ -0,0 +1,541 @@
+//! REST API for scripts and bots, changing the same players and broadcasting the same events as the websocket
+//!
+//! Every route needs an `Authorization: Bearer <token>` header with an api token, a session,
+//! or a twitch access token, whose role is high enough. Changes are recorded in the audit log
+
+use axum::{
+    Json, Router,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{
+        StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        request::Parts,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    AppState, Event, MediaType, Message, Position, Role, ServerPlayer,
+    audit::{self, Filter},
+    auth::Identity,
+    media::{MediaError, check_link, store_data_url},
+    server::ssr::{NO_SENDER_ID, apply_message, insert_new_player},
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/players", get(list_players).post(create_player))
+        .route(
+            "/api/players/{name}",
+            get(get_player).patch(update_player).delete(delete_player),
+        )
+        .route("/api/players/{name}/up", post(move_player_up))
+        .route("/api/players/{name}/down", post(move_player_down))
+        .route("/api/scenes", get(list_scenes))
+        .route("/api/scenes/{name}", put(save_scene).delete(delete_scene))
+        .route("/api/scenes/{name}/trigger", post(trigger_scene))
+        .route("/api/audit", get(export_audit_log))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("missing or invalid access token")]
+    Unauthorized,
+    #[error("this needs the {0:?} role")]
+    Forbidden(Role),
+    #[error("no player named {0:?}")]
+    PlayerNotFound(String),
+    #[error("{holder} is editing {player_name:?} right now")]
+    Locked { player_name: String, holder: String },
+    #[error("no scene named {0:?}")]
+    SceneNotFound(String),
+    #[error("couldn't save the scenes")]
+    ScenesUnsaved(std::io::Error),
+    #[error("no media at {0:?} in the library")]
+    MediaNotFound(String),
+    #[error(transparent)]
+    Media(#[from] MediaError),
+    #[error("the audit log can't be exported as {0:?}, only as \"jsonl\"")]
+    UnsupportedFormat(String),
+    #[error("couldn't read the audit log")]
+    AuditLogUnreadable(std::io::Error),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::PlayerNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Locked { .. } => StatusCode::CONFLICT,
+            ApiError::SceneNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::ScenesUnsaved(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::MediaNotFound(_) => StatusCode::BAD_REQUEST,
+            ApiError::Media(MediaError::TooLarge { .. }) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Media(MediaError::Io(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Media(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedFormat(_) => StatusCode::BAD_REQUEST,
+            ApiError::AuditLogUnreadable(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (
+            status,
+            Json(serde_json::json!({ "error": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+/// The role of whoever sent the request, and the name they're recorded under in the audit log
+pub struct Authorized {
+    role: Role,
+    actor: String,
+}
+
+impl Authorized {
+    fn require(&self, role: Role) -> Result<(), ApiError> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(role))
+        }
+    }
+
+    async fn record(&self, state: &AppState, message: &Message) {
+        state
+            .audit_log
+            .write()
+            .await
+            .record(None, &self.actor, message)
+            .await;
+    }
+
+    async fn record_action(&self, state: &AppState, action: &'static str, details: String) {
+        state
+            .audit_log
+            .write()
+            .await
+            .record_action(None, &self.actor, action, details)
+            .await;
+    }
+}
+
+impl FromRequestParts<AppState> for Authorized {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, ApiError> {
+        let access_token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .ok_or(ApiError::Unauthorized)?;
+
+        let identity = Identity::authorize(state, access_token.to_string())
+            .await
+            .map_err(|e| {
+                tracing::debug!("api request not authorized: {e}");
+                ApiError::Unauthorized
+            })?;
+
+        let role = identity.role(state).await.ok_or(ApiError::Unauthorized)?;
+
+        Ok(Authorized {
+            role,
+            actor: identity.name(state).await,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPlayer {
+    name: String,
+    /// Text, or the image or video as a base64 data url, an http(s) link or a link from the media library
+    data: String,
+    media_type: MediaType,
+    position: Position,
+    width: i32,
+    /// Leaving it out keeps the aspect ratio of the media
+    #[serde(default)]
+    height: Option<i32>,
+}
+
+/// Fields that are left out stay the same
+#[derive(Debug, Deserialize)]
+pub struct PlayerUpdate {
+    position: Option<Position>,
+    width: Option<i32>,
+    /// `null` sets the height to auto, keeping the aspect ratio
+    #[serde(default, deserialize_with = "present")]
+    height: Option<Option<i32>>,
+    horizontal_flip: Option<bool>,
+}
+
+/// Which audit log entries to export, the filters work like the ones on the control page
+#[derive(Debug, Deserialize)]
+pub struct AuditExport {
+    /// Only `jsonl` for now
+    format: Option<String>,
+    actor: Option<String>,
+    action: Option<String>,
+}
+
+/// Tells a field set to `null` apart from a missing one
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<i32>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Called while holding the players lock, a snapshot taken in between would have the change
+/// without its revision
+fn broadcast(state: &AppState, mut events: Vec<Event>) {
+    let event = match events.len() {
+        0 => return,
+        1 => events.remove(0),
+        _ => Event::Batch(events),
+    };
+    // events caused by the api go to every socket
+    let _ = state.broadcaster.send((NO_SENDER_ID, event));
+}
+
+/// Lists the players from the bottom to the top
+async fn list_players(
+    authorized: Authorized,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ServerPlayer>>, ApiError> {
+    authorized.require(Role::Viewer)?;
+
+    Ok(Json(state.players.read().await.values().cloned().collect()))
+}
+
+async fn get_player(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ServerPlayer>, ApiError> {
+    authorized.require(Role::Viewer)?;
+
+    state
+        .players
+        .read()
+        .await
+        .get(&name)
+        .cloned()
+        .map(Json)
+        .ok_or(ApiError::PlayerNotFound(name))
+}
+
+/// Adds a player on top, its name gets a suffix if it's already taken
+async fn create_player(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Json(new_player): Json<NewPlayer>,
+) -> Result<(StatusCode, Json<ServerPlayer>), ApiError> {
+    authorized.require(Role::Editor)?;
+
+    let data = match new_player.media_type {
+        MediaType::Text => new_player.data,
+        _ => media_source(&state, &new_player.name, new_player.data).await?,
+    };
+    let mut players = state.players.write().await;
+    let player = insert_new_player(
+        &mut players,
+        new_player.name,
+        data,
+        new_player.media_type,
+        new_player.position,
+        new_player.width,
+        new_player.height,
+    );
+    let message = Message::NewMedia {
+        name: player.name.clone(),
+        data: player.data.clone(),
+        media_type: player.media_type,
+        position: player.position,
+        width: player.width,
+        height: player.height,
+    };
+    broadcast(&state, vec![Event::NewPlayer(player.clone())]);
+    drop(players);
+    authorized.record(&state, &message).await;
+
+    Ok((StatusCode::CREATED, Json(player)))
+}
+
+/// Where a new player's media is served from, data urls are stored in the media library like uploads
+async fn media_source(state: &AppState, name: &str, data: String) -> Result<String, ApiError> {
+    if data.starts_with("data:") {
+        // base64 makes the data url about a third bigger than the file
+        if data.len() / 4 * 3 > state.max_upload_size {
+            return Err(MediaError::TooLarge {
+                limit: state.max_upload_size,
+            }
+            .into());
+        }
+        let (asset, is_new) = store_data_url(&state.media_library, name.to_string(), &data).await?;
+        if is_new {
+            let _ = state
+                .broadcaster
+                .send((NO_SENDER_ID, Event::MediaAdded(asset.clone())));
+        }
+        return Ok(asset.src);
+    }
+
+    if data.starts_with('/') {
+        if !state.media_library.read().await.serves(&data) {
+            return Err(ApiError::MediaNotFound(data));
+        }
+        return Ok(data);
+    }
+
+    check_link(&data)?;
+    Ok(data)
+}
+
+async fn update_player(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(update): Json<PlayerUpdate>,
+) -> Result<Json<ServerPlayer>, ApiError> {
+    authorized.require(Role::Moderator)?;
+
+    // checked under the players lock, so a lease can't be granted in between
+    let mut players = state.players.write().await;
+    let Some(player) = players.get(&name) else {
+        return Err(ApiError::PlayerNotFound(name));
+    };
+    check_lease(&state, &name).await?;
+
+    let mut messages = Vec::new();
+    if let Some(new_position) = update.position {
+        messages.push(Message::SetPosition {
+            player_name: name.clone(),
+            new_position,
+        });
+    }
+    if update.width.is_some() || update.height.is_some() {
+        messages.push(Message::SetSize {
+            player_name: name.clone(),
+            width: update.width.unwrap_or(player.width),
+            height: update.height.unwrap_or(player.height),
+        });
+    }
+    if let Some(is_flipped) = update.horizontal_flip {
+        messages.push(Message::FlipPlayerHorizontally {
+            player_name: name.clone(),
+            is_flipped,
+        });
+    }
+
+    let (applied, events): (Vec<_>, Vec<_>) = messages
+        .into_iter()
+        .filter_map(|message| Some((message.clone(), apply_message(&mut players, message)?)))
+        .unzip();
+    let player = players[&name].clone();
+    broadcast(&state, events);
+    drop(players);
+    for message in &applied {
+        authorized.record(&state, message).await;
+    }
+
+    Ok(Json(player))
+}
+
+/// Players someone is dragging or resizing on the control page can't be changed,
+/// called while holding the players lock
+async fn check_lease(state: &AppState, player_name: &str) -> Result<(), ApiError> {
+    state
+        .leases
+        .write()
+        .await
+        .check(NO_SENDER_ID, player_name)
+        .map_err(|lease| ApiError::Locked {
+            player_name: player_name.to_string(),
+            holder: lease.holder,
+        })
+}
+
+/// Applies a message that only needs the player's name, like deleting or reordering it
+async fn apply_to_player(
+    authorized: Authorized,
+    state: &AppState,
+    name: String,
+    message: impl FnOnce(String) -> Message,
+) -> Result<StatusCode, ApiError> {
+    let message = message(name.clone());
+    if let Some(role) = message.required_role() {
+        authorized.require(role)?;
+    }
+
+    let mut players = state.players.write().await;
+    if !players.contains_key(&name) {
+        return Err(ApiError::PlayerNotFound(name));
+    }
+    check_lease(state, &name).await?;
+
+    let event = apply_message(&mut players, message.clone());
+    let is_applied = event.is_some();
+    broadcast(state, event.into_iter().collect());
+    drop(players);
+    if is_applied {
+        authorized.record(state, &message).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_player(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    apply_to_player(authorized, &state, name, |player_name| {
+        Message::DeletePlayer { player_name }
+    })
+    .await
+}
+
+async fn move_player_up(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    apply_to_player(authorized, &state, name, |player_name| {
+        Message::MovePlayerUp { player_name }
+    })
+    .await
+}
+
+async fn move_player_down(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    apply_to_player(authorized, &state, name, |player_name| {
+        Message::MovePlayerDown { player_name }
+    })
+    .await
+}
+
+async fn list_scenes(
+    authorized: Authorized,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    authorized.require(Role::Viewer)?;
+
+    Ok(Json(state.scenes.read().await.names()))
+}
+
+/// Stores every player as they are now under `name`, replacing a scene with the same name
+async fn save_scene(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    authorized.require(Role::Editor)?;
+
+    let players = state.players.read().await.clone();
+    state
+        .scenes
+        .write()
+        .await
+        .set(name.clone(), players)
+        .await
+        .map_err(|e| {
+            tracing::error!("couldn't save the scene {name:?}: {e}");
+            ApiError::ScenesUnsaved(e)
+        })?;
+    authorized
+        .record_action(&state, "SaveScene", format!("{name:?}"))
+        .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replaces every player with the ones in the scene, unless someone is editing one of them,
+/// players showing media that was purged since are left out
+async fn trigger_scene(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    authorized.require(Role::Editor)?;
+
+    let Some(scene) = state.scenes.read().await.get(&name).cloned() else {
+        return Err(ApiError::SceneNotFound(name));
+    };
+    let mut players = state.players.write().await;
+    for player_name in players.keys() {
+        check_lease(&state, player_name).await?;
+    }
+    // the library is locked after the players like when purging, so nothing is purged in between
+    let library = state.media_library.read().await;
+    let scene = scene
+        .into_iter()
+        .filter(|(player_name, player)| {
+            let is_missing = player.media_type != MediaType::Text
+                && player.data.starts_with('/')
+                && !library.serves(&player.data);
+            if is_missing {
+                tracing::warn!(
+                    "leaving out {player_name:?} of the scene {name:?}, its media was purged"
+                );
+            }
+            !is_missing
+        })
+        .collect();
+    drop(library);
+    *players = scene;
+    broadcast(&state, vec![Event::AllPlayers(players.clone())]);
+    drop(players);
+    authorized
+        .record_action(&state, "TriggerScene", format!("{name:?}"))
+        .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_scene(
+    authorized: Authorized,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    authorized.require(Role::Editor)?;
+
+    let removed = state.scenes.write().await.remove(&name).await;
+    match removed {
+        Ok(true) => {}
+        Ok(false) => return Err(ApiError::SceneNotFound(name)),
+        Err(e) => {
+            tracing::error!("couldn't delete the scene {name:?}: {e}");
+            return Err(ApiError::ScenesUnsaved(e));
+        }
+    }
+    authorized
+        .record_action(&state, "DeleteScene", format!("{name:?}"))
+        .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Every matching audit log entry as json lines, oldest first, including the ones too old to be searched
+async fn export_audit_log(
+    authorized: Authorized,
+    Query(export): Query<AuditExport>,
+) -> Result<Response, ApiError> {
+    authorized.require(Role::Moderator)?;
+    if let Some(format) = export.format
+        && format != "jsonl"
+    {
+        return Err(ApiError::UnsupportedFormat(format));
+    }
+
+    let filter = Filter::new(export.actor.as_deref(), export.action.as_deref());
+    let log = audit::export(&filter).await.map_err(|e| {
+        tracing::error!("couldn't export the audit log: {e}");
+        ApiError::AuditLogUnreadable(e)
+    })?;
+    Ok(([(CONTENT_TYPE, "application/jsonl")], log).into_response())
+}
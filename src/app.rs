@@ -1,11 +1,17 @@
-use crate::{Event, Player, control_page::ControlPage, home_page::HomePage};
+use crate::{
+    Event, KICKED_CLOSE_CODE, Message, PROTOCOL_VERSION, Player, RELOAD_CLOSE_CODE,
+    control_page::ControlPage, home_page::HomePage, settings_page::SettingsPage,
+};
 use codee::binary::BincodeSerdeCodec;
 use indexmap::IndexMap;
 use leptos::prelude::*;
 use leptos_meta::*;
 use leptos_router::components::{Route, Router, Routes};
 use leptos_router::*;
-use leptos_use::{UseWebSocketReturn, core::ConnectionReadyState, use_websocket};
+use leptos_use::{
+    ReconnectLimit, UseWebSocketOptions, UseWebSocketReturn, core::ConnectionReadyState,
+    use_websocket_with_options,
+};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -14,6 +20,8 @@ pub struct WebsocketContext {
     send: Arc<dyn Fn(&Vec<u8>) + Sync + Send>, // use Arc to make it easily cloneable
     open: Arc<dyn Fn() + Sync + Send>,
     pub ready_state: Signal<ConnectionReadyState>,
+    /// Why a moderator disconnected this page, it stays disconnected until it's reloaded
+    pub kicked: Signal<Option<String>>,
 }
 
 impl WebsocketContext {
@@ -22,12 +30,14 @@ impl WebsocketContext {
         send: Arc<dyn Fn(&Vec<u8>) + Sync + Send>,
         open: Arc<dyn Fn() + Sync + Send>,
         ready_state: Signal<ConnectionReadyState>,
+        kicked: Signal<Option<String>>,
     ) -> Self {
         Self {
             message,
             send,
             open,
             ready_state,
+            kicked,
         }
     }
 
@@ -72,22 +82,36 @@ pub fn App() -> impl IntoView {
         *set_ws_url.write_untracked() = base_ws_url;
     });
 
+    let (kicked, set_kicked) = signal(None::<String>);
+
     let UseWebSocketReturn {
         message,
         send,
         ready_state,
         open,
         ..
-    } = use_websocket::<Vec<u8>, Vec<u8>, BincodeSerdeCodec>(&format!(
-        "{}/ws",
-        ws_url.get_untracked()
-    ));
+    } = use_websocket_with_options::<Vec<u8>, Vec<u8>, BincodeSerdeCodec, _, _>(
+        &format!("{}/ws?v={PROTOCOL_VERSION}", ws_url.get_untracked()),
+        UseWebSocketOptions::default()
+            // the pages reconnect on their own, unless they were kicked
+            .reconnect_limit(ReconnectLimit::Limited(0))
+            .on_close(move |event| {
+                // the server was updated, only a reload gets the new client
+                if event.code() == RELOAD_CLOSE_CODE {
+                    let _ = window().location().reload();
+                }
+                if event.code() == KICKED_CLOSE_CODE {
+                    set_kicked(Some(event.reason()));
+                }
+            }),
+    );
 
     provide_context(WebsocketContext::new(
         message,
         Arc::new(send.clone()),
         Arc::new(open.clone()),
         ready_state,
+        kicked.into(),
     ));
 
     view! {
@@ -100,80 +124,125 @@ pub fn App() -> impl IntoView {
                 <Routes fallback=|| { "Not found" }>
                     <Route path=path!("/") view=HomePage/>
                     <Route path=path!("/control") view=ControlPage/>
+                    <Route path=path!("/control/settings") view=SettingsPage/>
                 </Routes>
             </main>
         </Router>
     }
 }
 
+/// Asks for what changed since the last revision seen, or for every player if there's none yet
+pub fn request_players(websocket: &WebsocketContext, revision: StoredValue<Option<u64>>) {
+    let message = match revision.get_value() {
+        Some(revision) => Message::GetChangesSince { revision },
+        None => Message::GetAllPlayers,
+    };
+    websocket.send(&bincode::serialize(&message).unwrap());
+}
+
 pub fn handle_websocket_message(
     websocket: WebsocketContext,
     // owner: Owner,
     set_players: WriteSignal<IndexMap<String, Player>>,
+    revision: StoredValue<Option<u64>>,
 ) {
     if let Some(message) = websocket.message.get() {
-        match bincode::deserialize::<Event>(&message).unwrap() {
-            Event::AllPlayers(incoming_players) => {
-                // leptos::with_owner(owner, || {
-                let local_players = incoming_players
-                    .into_iter()
-                    .map(|(n, p)| (n, Player::from(p)))
-                    .collect();
-                set_players.set(local_players);
-                // });
+        match bincode::deserialize::<Event>(&message) {
+            Ok(Event::Revision(new_revision)) => revision.set_value(Some(new_revision)),
+            Ok(event) => apply_event(event, set_players),
+            // events added by a newer server, ignoring them beats crashing the overlay
+            Err(e) => tracing::warn!("couldn't decode event: {e}"),
+        }
+    }
+}
+
+pub(crate) fn apply_event(event: Event, set_players: WriteSignal<IndexMap<String, Player>>) {
+    match event {
+        Event::AllPlayers(incoming_players) => {
+            // leptos::with_owner(owner, || {
+            let local_players = incoming_players
+                .into_iter()
+                .map(|(n, p)| (n, Player::from(p)))
+                .collect();
+            set_players.set(local_players);
+            // });
+        }
+        Event::NewPlayer(player) => {
+            // leptos::with_owner(owner, || {
+            let player = Player::from(player);
+            set_players.update(|players| {
+                players.insert(player.name.get_untracked(), player);
+            });
+            // });
+        }
+        Event::PositionUpdated {
+            player_name,
+            new_position,
+        } => set_players.update(|players| {
+            if let Some(player) = players.get_mut(&player_name) {
+                player.position.set(new_position);
             }
-            Event::NewPlayer(player) => {
-                // leptos::with_owner(owner, || {
-                let player = Player::from(player);
-                set_players.update(|players| {
-                    players.insert(player.name.get_untracked(), player);
-                });
-                // });
+        }),
+        Event::SizeUpdated {
+            player_name,
+            new_width,
+            new_height,
+        } => set_players.update(|players| {
+            if let Some(player) = players.get_mut(&player_name) {
+                player.width.set(new_width);
+                player.height.set(new_height);
             }
-            Event::PositionUpdated {
-                player_name,
-                new_position,
-            } => set_players.update(|players| {
-                if let Some(player) = players.get_mut(&player_name) {
-                    player.position.set(new_position);
-                }
-            }),
-            Event::SizeUpdated {
-                player_name,
-                new_width,
-                new_height,
-            } => set_players.update(|players| {
-                if let Some(player) = players.get_mut(&player_name) {
-                    player.width.set(new_width);
-                    player.height.set(new_height);
-                }
-            }),
-            Event::PlayerDeleted { player_name } => set_players.update(|players| {
-                players.shift_remove(&player_name);
-            }),
-            Event::PlayerMovedUp { player_name } => set_players.update(|players| {
-                tracing::info!("moving {player_name} up");
-                if let Some(s) = players.get_index_of(&player_name) {
-                    players.swap_indices(s, s - 1);
-                }
-            }),
-            Event::PlayerMovedDown { player_name } => set_players.update(|players| {
-                tracing::info!("moving {player_name} down");
-                if let Some(s) = players.get_index_of(&player_name) {
-                    players.swap_indices(s, s + 1);
-                }
-            }),
-            Event::FlipPlayerHorizontally {
-                player_name,
-                is_flipped,
-            } => set_players.update(|players| {
-                if let Some(player) = players.get_mut(&player_name) {
-                    player
-                        .horizontal_flip
-                        .update(|flipped| *flipped = is_flipped);
-                }
-            }),
-            Event::Pong => {}
+        }),
+        Event::PlayerDeleted { player_name } => set_players.update(|players| {
+            players.shift_remove(&player_name);
+        }),
+        Event::PlayerMovedUp { player_name } => set_players.update(|players| {
+            tracing::info!("moving {player_name} up");
+            if let Some(s) = players.get_index_of(&player_name) {
+                players.swap_indices(s, s - 1);
+            }
+        }),
+        Event::PlayerMovedDown { player_name } => set_players.update(|players| {
+            tracing::info!("moving {player_name} down");
+            if let Some(s) = players.get_index_of(&player_name) {
+                players.swap_indices(s, s + 1);
+            }
+        }),
+        Event::FlipPlayerHorizontally {
+            player_name,
+            is_flipped,
+        } => set_players.update(|players| {
+            if let Some(player) = players.get_mut(&player_name) {
+                player
+                    .horizontal_flip
+                    .update(|flipped| *flipped = is_flipped);
+            }
+        }),
+        Event::Batch(events) => {
+            for event in events {
+                apply_event(event, set_players);
+            }
+        }
+        // the media library is only shown in the control page, which handles these itself
+        Event::MediaLibrary(_)
+        | Event::MediaAdded(_)
+        | Event::MediaPurged { .. }
+        | Event::UploadProgress { .. }
+        | Event::UploadRejected { .. }
+        | Event::ApiTokens(_)
+        | Event::ApiTokenCreated { .. }
+        | Event::Authorized { .. }
+        | Event::Roles(_)
+        | Event::AuditLog(_)
+        | Event::Presence(_)
+        | Event::PresenceUpdated(_)
+        | Event::Leases(_)
+        | Event::Ack { .. }
+        | Event::Revision(_)
+        | Event::Connections(_) => {}
+        Event::MediaRejected { name, reason } => {
+            tracing::warn!("couldn't add {name}: {reason}");
         }
+        Event::Pong => {}
     }
 }
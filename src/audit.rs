@@ -0,0 +1,300 @@
+//! Append-only log of every change made to the overlay, so it's clear who did what
+
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::RwLock,
+};
+
+use crate::{AuditEntry, Message};
+
+/// Where the log is stored, one json object per line
+pub const AUDIT_LOG_FILE: &str = "audit.jsonl";
+/// Most entries sent for one [`Message::GetAuditLog`]
+const PAGE_SIZE: usize = 500;
+/// Most entries kept in memory, older ones are only read from [`AUDIT_LOG_FILE`] when searched
+const KEPT_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    /// The newest entries, oldest first
+    entries: VecDeque<AuditEntry>,
+    /// Whether [`AUDIT_LOG_FILE`] has entries that aren't kept in memory
+    has_older: bool,
+}
+
+impl AuditLog {
+    /// Loads the newest entries from [`AUDIT_LOG_FILE`]
+    pub async fn load() -> Self {
+        let mut log = Self::default();
+        if let Err(e) = read_entries(|entry| log.keep(entry)).await {
+            tracing::error!("couldn't read the audit log: {e}");
+        }
+
+        log
+    }
+
+    fn keep(&mut self, entry: AuditEntry) {
+        self.entries.push_back(entry);
+        if self.entries.len() > KEPT_ENTRIES {
+            self.entries.pop_front();
+            self.has_older = true;
+        }
+    }
+
+    /// Records the changes `message` makes, messages that only read something aren't recorded
+    pub async fn record(&mut self, socket_id: Option<u32>, actor: &str, message: &Message) {
+        self.record_described(socket_id, actor, describe(message))
+            .await;
+    }
+
+    /// Records a change there's no [`Message`] for, like triggering a scene through the api
+    pub async fn record_action(
+        &mut self,
+        socket_id: Option<u32>,
+        actor: &str,
+        action: &'static str,
+        details: String,
+    ) {
+        self.record_described(socket_id, actor, vec![(action, details)])
+            .await;
+    }
+
+    async fn record_described(
+        &mut self,
+        socket_id: Option<u32>,
+        actor: &str,
+        described: Vec<(&'static str, String)>,
+    ) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let entries = described
+            .into_iter()
+            .map(|(action, details)| AuditEntry {
+                at,
+                socket_id,
+                actor: actor.to_string(),
+                action: action.to_string(),
+                details,
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return;
+        }
+
+        if let Err(e) = append(&entries).await {
+            tracing::error!("couldn't write the audit log: {e}");
+        }
+        for entry in entries {
+            self.keep(entry);
+        }
+    }
+}
+
+/// Case-insensitive parts the actor and the action of an entry have to contain
+#[derive(Debug, Default)]
+pub struct Filter {
+    actor: Option<String>,
+    action: Option<String>,
+}
+
+impl Filter {
+    pub fn new(actor: Option<&str>, action: Option<&str>) -> Self {
+        Self {
+            actor: actor.map(str::to_lowercase),
+            action: action.map(str::to_lowercase),
+        }
+    }
+
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor
+            .as_ref()
+            .is_none_or(|actor| entry.actor.to_lowercase().contains(actor))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| entry.action.to_lowercase().contains(action))
+    }
+}
+
+/// The newest entries matching `filter`, newest first
+///
+/// Only reads [`AUDIT_LOG_FILE`] when the entries in memory don't fill a page, without holding the lock
+pub async fn search(log: &RwLock<AuditLog>, filter: &Filter) -> Vec<AuditEntry> {
+    let log = log.read().await;
+    let found = log
+        .entries
+        .iter()
+        .rev()
+        .filter(|entry| filter.matches(entry))
+        .take(PAGE_SIZE)
+        .cloned()
+        .collect::<Vec<_>>();
+    if found.len() == PAGE_SIZE || !log.has_older {
+        return found;
+    }
+    drop(log);
+
+    let mut newest = VecDeque::with_capacity(PAGE_SIZE);
+    let read = read_entries(|entry| {
+        if filter.matches(&entry) {
+            if newest.len() == PAGE_SIZE {
+                newest.pop_front();
+            }
+            newest.push_back(entry);
+        }
+    })
+    .await;
+    match read {
+        Ok(()) => newest.into_iter().rev().collect(),
+        Err(e) => {
+            tracing::error!("couldn't read the audit log: {e}");
+            found
+        }
+    }
+}
+
+/// Every entry matching `filter` as json lines, oldest first, including the ones not kept in memory
+pub async fn export(filter: &Filter) -> std::io::Result<String> {
+    let mut matching = Vec::new();
+    read_entries(|entry| {
+        if filter.matches(&entry) {
+            matching.push(entry);
+        }
+    })
+    .await?;
+
+    Ok(json_lines(&matching))
+}
+
+/// Reads [`AUDIT_LOG_FILE`] line by line, skipping lines that can't be parsed
+async fn read_entries(mut on_entry: impl FnMut(AuditEntry)) -> std::io::Result<()> {
+    let file = match tokio::fs::File::open(AUDIT_LOG_FILE).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => on_entry(entry),
+            Err(e) => tracing::warn!("skipping audit log line that couldn't be parsed: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn json_lines(entries: &[AuditEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| serde_json::to_string(entry).expect("entries should serialize") + "\n")
+        .collect()
+}
+
+async fn append(entries: &[AuditEntry]) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_LOG_FILE)
+        .await?;
+    file.write_all(json_lines(entries).as_bytes()).await?;
+    file.flush().await
+}
+
+/// The action and details of every change in `message`, batches are recorded as their parts
+fn describe(message: &Message) -> Vec<(&'static str, String)> {
+    let described = match message {
+        Message::Batch(messages) => return messages.iter().flat_map(describe).collect(),
+        Message::Sequenced { message, .. } => return describe(message),
+        Message::Ping
+        | Message::Authorize(_)
+        | Message::GetAllPlayers
+        | Message::GetChangesSince { .. }
+        | Message::GetMediaLibrary
+        | Message::GetApiTokens
+        | Message::GetRoles
+        | Message::GetAuditLog { .. }
+        | Message::GetConnections
+        | Message::UpdatePresence { .. }
+        | Message::AcquireLeases { .. }
+        | Message::ReleaseLeases
+        // only the final values of a drag are recorded
+        | Message::DragUpdate(_)
+        // an upload is recorded as its start once it's complete, the chunks are only its data
+        | Message::UploadChunk { .. }
+        | Message::CancelUpload { .. } => return Vec::new(),
+        Message::SetPosition {
+            player_name,
+            new_position,
+        } => (
+            "SetPosition",
+            format!("{player_name:?} to {}, {}", new_position.x, new_position.y),
+        ),
+        Message::SetSize {
+            player_name,
+            width,
+            height,
+        } => (
+            "SetSize",
+            match height {
+                Some(height) => format!("{player_name:?} to {width}x{height}"),
+                None => format!("{player_name:?} to {width} wide"),
+            },
+        ),
+        Message::NewMedia {
+            name, media_type, ..
+        } => ("NewMedia", format!("{name:?} ({media_type:?})")),
+        Message::DeletePlayer { player_name } => ("DeletePlayer", format!("{player_name:?}")),
+        Message::MovePlayerUp { player_name } => ("MovePlayerUp", format!("{player_name:?}")),
+        Message::MovePlayerDown { player_name } => ("MovePlayerDown", format!("{player_name:?}")),
+        Message::FlipPlayerHorizontally {
+            player_name,
+            is_flipped,
+        } => (
+            "FlipPlayerHorizontally",
+            format!("{player_name:?}, flipped: {is_flipped}"),
+        ),
+        Message::NewMediaFromUrl { url, .. } => ("NewMediaFromUrl", url.clone()),
+        Message::StartUpload {
+            name,
+            content_type,
+            size,
+            ..
+        } => (
+            "StartUpload",
+            format!("{name:?} ({content_type}, {size} bytes)"),
+        ),
+        Message::NewMediaFromLibrary { asset_id, .. } => {
+            ("NewMediaFromLibrary", format!("asset {asset_id}"))
+        }
+        Message::PurgeMedia { asset_id } => ("PurgeMedia", format!("asset {asset_id}")),
+        Message::DuplicatePlayer { player_name, .. } => {
+            ("DuplicatePlayer", format!("{player_name:?}"))
+        }
+        Message::CreateApiToken { name, scope } => {
+            ("CreateApiToken", format!("{name:?} with the {scope:?} scope"))
+        }
+        Message::RevokeApiToken { token_id } => ("RevokeApiToken", format!("token {token_id}")),
+        Message::KickConnection { socket_id } => ("KickConnection", format!("socket {socket_id}")),
+        Message::SetRole { login, role } => (
+            "SetRole",
+            match role {
+                Some(role) => format!("{login} to {role:?}"),
+                None => format!("removed the role of {login}"),
+            },
+        ),
+    };
+
+    vec![described]
+}
@@ -0,0 +1,365 @@
+//! Twitch logins turned into signed sessions, so twitch is only asked once in a while instead of on every connection
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    AppState, ConnectionKind, Role, Scope,
+    hashing::{hash, hex, unhex},
+};
+
+const TWITCH_CLIENT_ID: &str = "48mas39k4vcamtq5fy33r7qegf13l9";
+/// Its broadcaster owns the overlay, its moderators can use the control page
+pub const CHANNEL: &str = "sadmadladsalman";
+/// How long twitch's answer for an access token is reused
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const SESSION_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Set `SESSION_SECRET` to keep sessions valid across restarts
+static SESSION_SECRET: LazyLock<Vec<u8>> =
+    LazyLock::new(|| match std::env::var("SESSION_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+        _ => {
+            let mut secret = vec![0; 32];
+            rand::rng().fill_bytes(&mut secret);
+            secret
+        }
+    });
+
+/// Results of earlier twitch checks by the hash of the access token
+static CACHE: LazyLock<Mutex<HashMap<String, (Instant, User)>>> = LazyLock::new(Default::default);
+
+/// Someone who logged in with twitch, their role is looked up in [`RoleStore`](crate::roles::RoleStore)
+#[derive(Debug, Clone)]
+pub struct User {
+    pub login: String,
+    /// Whether they moderate [`CHANNEL`], the broadcaster counts as one
+    pub is_moderator: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("couldn't ask twitch: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("twitch didn't return a user for the access token")]
+    MissingUser,
+    #[error("{0} isn't allowed to use the control page")]
+    NoRole(String),
+    #[error("invalid session")]
+    InvalidSession,
+    #[error("the session expired, log in again")]
+    SessionExpired,
+}
+
+#[derive(Debug, Deserialize)]
+struct TwitchResponse<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TwitchUser {
+    id: String,
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModeratedChannel {
+    broadcaster_login: String,
+}
+
+/// Returns who the access token belongs to
+pub async fn check_twitch_token(access_token: &str) -> Result<User, AuthError> {
+    let key = hash(access_token.as_bytes());
+    let cached = CACHE
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|(checked_at, _)| checked_at.elapsed() < CACHE_TTL)
+        .map(|(_, user)| user.clone());
+    if let Some(user) = cached {
+        return Ok(user);
+    }
+
+    let user = ask_twitch(access_token).await?;
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|_, (checked_at, _)| checked_at.elapsed() < CACHE_TTL);
+    cache.insert(key, (Instant::now(), user.clone()));
+
+    Ok(user)
+}
+
+async fn ask_twitch(access_token: &str) -> Result<User, AuthError> {
+    let client = reqwest::Client::new();
+    let user = client
+        .get("https://api.twitch.tv/helix/users")
+        .bearer_auth(access_token)
+        .header("Client-Id", TWITCH_CLIENT_ID)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TwitchResponse<TwitchUser>>()
+        .await?
+        .data
+        .into_iter()
+        .next()
+        .ok_or(AuthError::MissingUser)?;
+
+    if user.login == CHANNEL {
+        return Ok(User {
+            login: user.login,
+            is_moderator: true,
+        });
+    }
+
+    let channels = client
+        .get(format!(
+            "https://api.twitch.tv/helix/moderation/channels?user_id={}&first=100",
+            user.id
+        ))
+        .bearer_auth(access_token)
+        .header("Client-Id", TWITCH_CLIENT_ID)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TwitchResponse<ModeratedChannel>>()
+        .await?;
+
+    Ok(User {
+        login: user.login,
+        is_moderator: channels
+            .data
+            .iter()
+            .any(|channel| channel.broadcaster_login == CHANNEL),
+    })
+}
+
+/// Creates a session for `user`, formatted as `login.is_moderator.expiry.signature`
+///
+/// The role isn't part of it, so changing someone's role applies to their existing sessions too
+pub fn create_session(user: &User) -> String {
+    let expires_at = (SystemTime::now() + SESSION_LIFETIME)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let payload = format!("{}.{}.{expires_at}", user.login, user.is_moderator);
+    let signature = hex(&sign(&payload));
+
+    format!("{payload}.{signature}")
+}
+
+pub fn verify_session(session: &str) -> Result<User, AuthError> {
+    let (payload, signature) = session.rsplit_once('.').ok_or(AuthError::InvalidSession)?;
+    let signature = unhex(signature).ok_or(AuthError::InvalidSession)?;
+
+    let mut mac = mac();
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| AuthError::InvalidSession)?;
+
+    let mut parts = payload.split('.');
+    let (Some(login), Some(is_moderator), Some(expires_at), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AuthError::InvalidSession);
+    };
+    let is_moderator = is_moderator
+        .parse::<bool>()
+        .map_err(|_| AuthError::InvalidSession)?;
+    let expires_at = expires_at
+        .parse::<u64>()
+        .map_err(|_| AuthError::InvalidSession)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if expires_at < now {
+        return Err(AuthError::SessionExpired);
+    }
+
+    Ok(User {
+        login: login.to_string(),
+        is_moderator,
+    })
+}
+
+/// Accepts a session, or a twitch access token for scripts that don't log in first
+pub async fn check_session_or_twitch_token(token: &str) -> Result<User, AuthError> {
+    match verify_session(token) {
+        Ok(user) => Ok(user),
+        // only well formed but expired sessions are rejected right away, anything else might be a twitch token
+        Err(AuthError::SessionExpired) => Err(AuthError::SessionExpired),
+        Err(_) => check_twitch_token(token).await,
+    }
+}
+
+/// Who a socket or api request is authorized as
+#[derive(Clone)]
+pub enum Identity {
+    User(User),
+    /// Keeps the secret, so the token is looked up again and revoking it takes effect right away
+    ApiToken(String),
+}
+
+// the secret stays out of logs
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Identity::User(user) => f.debug_tuple("User").field(user).finish(),
+            Identity::ApiToken(_) => f.debug_tuple("ApiToken").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Identity {
+    /// Accepts an api token, a session or a twitch access token, as long as it comes with a role
+    pub async fn authorize(state: &AppState, token: String) -> Result<Identity, AuthError> {
+        let identity = if state.tokens.read().await.scope_of(&token).is_some() {
+            Identity::ApiToken(token)
+        } else {
+            Identity::User(check_session_or_twitch_token(&token).await?)
+        };
+
+        match (identity.role(state).await, identity) {
+            (Some(_), identity) => Ok(identity),
+            (None, Identity::User(user)) => Err(AuthError::NoRole(user.login)),
+            (None, Identity::ApiToken(_)) => Err(AuthError::InvalidSession),
+        }
+    }
+
+    /// `None` once the token is revoked or the user's role removed
+    pub async fn role(&self, state: &AppState) -> Option<Role> {
+        match self {
+            Identity::User(user) => state.roles.read().await.role_of(user),
+            Identity::ApiToken(secret) => state
+                .tokens
+                .read()
+                .await
+                .scope_of(secret)
+                .map(|scope| scope.role()),
+        }
+    }
+
+    /// Overlay tokens are meant for overlays, everyone else authorizes from the control page
+    pub async fn connection_kind(&self, state: &AppState) -> ConnectionKind {
+        match self {
+            Identity::ApiToken(secret)
+                if state.tokens.read().await.scope_of(secret) == Some(Scope::Overlay) =>
+            {
+                ConnectionKind::Overlay
+            }
+            _ => ConnectionKind::Control,
+        }
+    }
+
+    /// Who to name in the audit log, the twitch login or the name of the api token
+    pub async fn name(&self, state: &AppState) -> String {
+        match self {
+            Identity::User(user) => user.login.clone(),
+            Identity::ApiToken(secret) => match state.tokens.read().await.name_of(secret) {
+                Some(name) => format!("token {name}"),
+                None => "revoked token".to_string(),
+            },
+        }
+    }
+}
+
+fn mac() -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(&SESSION_SECRET).expect("hmac accepts keys of any length")
+}
+
+fn sign(payload: &str) -> Vec<u8> {
+    let mut mac = mac();
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User {
+            login: "someone".to_string(),
+            is_moderator: true,
+        }
+    }
+
+    /// Signs `payload` like [`create_session`], which can't make expired or malformed sessions
+    fn signed(payload: &str) -> String {
+        format!("{payload}.{}", hex(&sign(payload)))
+    }
+
+    #[test]
+    fn verifies_created_sessions() {
+        let verified = verify_session(&create_session(&user())).unwrap();
+
+        assert_eq!(verified.login, "someone");
+        assert!(verified.is_moderator);
+    }
+
+    #[test]
+    fn rejects_tampered_sessions() {
+        let session = create_session(&user());
+
+        let other_login = session.replacen("someone", CHANNEL, 1);
+        assert!(matches!(
+            verify_session(&other_login),
+            Err(AuthError::InvalidSession)
+        ));
+
+        let promoted = create_session(&User {
+            is_moderator: false,
+            ..user()
+        })
+        .replacen("false", "true", 1);
+        assert!(matches!(
+            verify_session(&promoted),
+            Err(AuthError::InvalidSession)
+        ));
+
+        let mut other_signature = session.clone();
+        let last = other_signature.pop().unwrap();
+        other_signature.push(if last == '0' { '1' } else { '0' });
+        assert!(matches!(
+            verify_session(&other_signature),
+            Err(AuthError::InvalidSession)
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_sessions() {
+        assert!(matches!(
+            verify_session(&signed("someone.true.1")),
+            Err(AuthError::SessionExpired)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_sessions() {
+        let malformed = [
+            String::new(),
+            "no signature".to_string(),
+            "someone.true.99999999999.not hex".to_string(),
+            "someone.true.99999999999.abc".to_string(),
+            signed("someone.true"),
+            signed("someone.true.99999999999.extra"),
+            signed("someone.maybe.99999999999"),
+            signed("someone.true.tomorrow"),
+        ];
+
+        for session in malformed {
+            assert!(
+                matches!(verify_session(&session), Err(AuthError::InvalidSession)),
+                "{session:?} should be invalid"
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Every connected socket, so moderators can see who's connected and disconnect them
+
+use std::{
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use indexmap::IndexMap;
+use tokio::sync::oneshot;
+
+use crate::{
+    AppState, ConnectionInfo, ConnectionKind, Role, auth::Identity, server::ssr::NO_SENDER_ID,
+};
+
+#[derive(Debug)]
+struct RegisteredConnection {
+    info: ConnectionInfo,
+    /// Who it was authorized as, looked up again when needed so role changes and revoked tokens count
+    identity: Option<Identity>,
+    /// Its receiver ends the socket's loop
+    kick: oneshot::Sender<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    next_id: u32,
+    connections: IndexMap<u32, RegisteredConnection>,
+}
+
+impl ConnectionRegistry {
+    /// Hands out the socket's id, the receiver gets who kicked it
+    pub fn register(&mut self, remote_address: SocketAddr) -> (u32, oneshot::Receiver<String>) {
+        let mut socket_id = self.next_id;
+        // ids wrap around eventually, skipping the reserved one and any still connected
+        while socket_id == NO_SENDER_ID || self.connections.contains_key(&socket_id) {
+            socket_id = socket_id.wrapping_add(1);
+        }
+        self.next_id = socket_id.wrapping_add(1);
+
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (kick, kicked) = oneshot::channel();
+        self.connections.insert(
+            socket_id,
+            RegisteredConnection {
+                info: ConnectionInfo {
+                    socket_id,
+                    kind: ConnectionKind::Overlay,
+                    identity: None,
+                    remote_address: remote_address.to_string(),
+                    connected_at,
+                },
+                identity: None,
+                kick,
+            },
+        );
+
+        (socket_id, kicked)
+    }
+
+    /// Remembers who uses the socket and what for
+    pub fn authorize(&mut self, socket_id: u32, identity: Identity, kind: ConnectionKind) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.info.kind = kind;
+            connection.identity = Some(identity);
+        }
+    }
+
+    /// The socket's role right now, `None` if it isn't authorized or connected
+    pub async fn role_of(&self, state: &AppState, socket_id: u32) -> Option<Role> {
+        let identity = self.connections.get(&socket_id)?.identity.as_ref()?;
+
+        identity.role(state).await
+    }
+
+    pub fn unregister(&mut self, socket_id: u32) {
+        self.connections.shift_remove(&socket_id);
+    }
+
+    /// Tells the socket to close and forgets it right away, returns whether it was connected
+    pub fn kick(&mut self, socket_id: u32, kicked_by: &str) -> bool {
+        self.connections
+            .shift_remove(&socket_id)
+            .is_some_and(|connection| connection.kick.send(kicked_by.to_string()).is_ok())
+    }
+
+    /// Named the way the audit log names them right now, a revoked token shows up as one
+    pub async fn connections(&self, state: &AppState) -> Vec<ConnectionInfo> {
+        let mut connections = Vec::with_capacity(self.connections.len());
+        for connection in self.connections.values() {
+            let mut info = connection.info.clone();
+            if let Some(identity) = &connection.identity {
+                info.identity = Some(identity.name(state).await);
+            }
+            connections.push(info);
+        }
+
+        connections
+    }
+}
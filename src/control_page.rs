@@ -1,28 +1,38 @@
-use base64::Engine;
 use codee::string::JsonSerdeCodec;
 use indexmap::IndexMap;
 use leptos::{
     ev::MouseEvent,
-    html::Input,
+    html::{A, Input},
     leptos_dom::helpers::{location, location_hash},
     prelude::*,
     task::spawn_local,
+    wasm_bindgen::{JsCast, JsValue},
+    web_sys,
 };
 use leptos_use::{
     core::ConnectionReadyState, storage::use_local_storage, use_event_listener, use_interval_fn,
     use_window,
 };
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
-    MediaType, Message, Player, Position,
-    app::{WebsocketContext, handle_websocket_message},
-    server::is_authorized,
+    ApiToken, AuditEntry, ConnectionInfo, ConnectionKind, Event, Lease, MAX_UPLOADS_PER_SOCKET,
+    MIN_PLAYER_SIZE, MediaAsset, MediaType, Message, Player, Position, Presence, Role, Scope,
+    UPLOAD_CHUNK_SIZE,
+    app::{WebsocketContext, apply_event, request_players},
+    server::{export_audit_log, log_in},
 };
 
 const ZOOM_SPEED: f64 = 0.002;
 const MIN_ZOOM: f64 = 0.1;
 const MAX_ZOOM: f64 = 5.0;
+/// How often the selection and mouse position are sent to the other editors, at most
+const PRESENCE_INTERVAL_MS: u64 = 50;
+/// Where duplicated media is placed relative to the original
+const DUPLICATE_OFFSET: Position = Position { x: 20, y: 20 };
+/// How close (in screen pixels) an edge has to be to a snap target to snap to it
+const SNAP_DISTANCE: f64 = 8.0;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct ScreenSize {
@@ -39,49 +49,541 @@ impl Default for ScreenSize {
     }
 }
 
-#[component]
-pub fn ControlPage() -> impl IntoView {
-    let (base_url, set_base_url) = signal(String::new());
-    Effect::new(move |_| {
-        let location = window().location();
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnapSettings {
+    to_screen: bool,
+    to_players: bool,
+    /// 0 means grid snapping is disabled
+    grid_size: i32,
+}
 
-        set_base_url(location.origin().unwrap());
-    });
+impl Default for SnapSettings {
+    fn default() -> Self {
+        Self {
+            to_screen: true,
+            to_players: true,
+            grid_size: 0,
+        }
+    }
+}
 
-    // auth (I guess)
-    let (authorized, set_authorized) = signal(false);
-    let (access_token, set_access_token, _) =
-        use_local_storage::<Option<String>, JsonSerdeCodec>("access_token");
+/// A line shown while dragging, in overlay coordinates
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Guide {
+    Vertical(i32),
+    Horizontal(i32),
+}
 
-    Effect::new(move |_| {
-        if let Some(hash) = location_hash() {
-            if hash.is_empty() {
+/// The box a player occupies, in overlay coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bounds {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+impl Bounds {
+    fn new(position: Position, width: i32, height: i32) -> Self {
+        Self {
+            left: position.x,
+            top: position.y,
+            right: position.x + width,
+            bottom: position.y + height,
+        }
+    }
+
+    fn of_player(player: &Player) -> Self {
+        let width = player.width.get_untracked();
+        let height = player
+            .height
+            .get_untracked()
+            .or_else(|| rendered_height(player, width))
+            // not rendered yet, so it doesn't cover anything
+            .unwrap_or_default();
+
+        Self::new(player.position.get_untracked(), width, height)
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        self.left < other.right
+            && self.right > other.left
+            && self.top < other.bottom
+            && self.bottom > other.top
+    }
+
+    fn center_x(&self) -> i32 {
+        (self.left + self.right) / 2
+    }
+
+    fn center_y(&self) -> i32 {
+        (self.top + self.bottom) / 2
+    }
+
+    fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+}
+
+/// The height of a player with an auto height, scaled from the aspect ratio it's rendered with
+fn rendered_height(player: &Player, width: i32) -> Option<i32> {
+    let element = player.element.get_untracked()?;
+    let (rendered_width, rendered_height) = (element.offset_width(), element.offset_height());
+    (rendered_width > 0)
+        .then(|| (width as f64 * rendered_height as f64 / rendered_width as f64).round() as i32)
+}
+
+/// Finds the closest target to any of `edges` within `threshold`
+///
+/// Returns the offset to add to the edges and the target that was snapped to
+fn snap_offset(edges: [i32; 3], targets: &[i32], threshold: i32) -> Option<(i32, i32)> {
+    edges
+        .iter()
+        .flat_map(|edge| targets.iter().map(move |target| (target - edge, *target)))
+        .filter(|(offset, _)| offset.abs() <= threshold)
+        .min_by_key(|(offset, _)| offset.abs())
+}
+
+/// One of the eight handles shown around a selected player
+#[derive(Clone, Copy, PartialEq)]
+enum Handle {
+    TopLeft,
+    Top,
+    TopRight,
+    Right,
+    BottomRight,
+    Bottom,
+    BottomLeft,
+    Left,
+}
+
+impl Handle {
+    const ALL: [Handle; 8] = [
+        Handle::TopLeft,
+        Handle::Top,
+        Handle::TopRight,
+        Handle::Right,
+        Handle::BottomRight,
+        Handle::Bottom,
+        Handle::BottomLeft,
+        Handle::Left,
+    ];
+
+    /// Which edges the handle drags, -1 for left/top, 1 for right/bottom and 0 for neither
+    fn direction(self) -> (i32, i32) {
+        match self {
+            Handle::TopLeft => (-1, -1),
+            Handle::Top => (0, -1),
+            Handle::TopRight => (1, -1),
+            Handle::Right => (1, 0),
+            Handle::BottomRight => (1, 1),
+            Handle::Bottom => (0, 1),
+            Handle::BottomLeft => (-1, 1),
+            Handle::Left => (-1, 0),
+        }
+    }
+
+    /// The handle on the other side horizontally, for players that are flipped
+    fn mirrored(self) -> Self {
+        match self {
+            Handle::TopLeft => Handle::TopRight,
+            Handle::TopRight => Handle::TopLeft,
+            Handle::Right => Handle::Left,
+            Handle::BottomRight => Handle::BottomLeft,
+            Handle::BottomLeft => Handle::BottomRight,
+            Handle::Left => Handle::Right,
+            Handle::Top | Handle::Bottom => self,
+        }
+    }
+
+    fn cursor(self) -> &'static str {
+        match self {
+            Handle::TopLeft | Handle::BottomRight => "nwse-resize",
+            Handle::TopRight | Handle::BottomLeft => "nesw-resize",
+            Handle::Top | Handle::Bottom => "ns-resize",
+            Handle::Left | Handle::Right => "ew-resize",
+        }
+    }
+}
+
+/// Resizes `start` as if `handle` was dragged by `delta`
+///
+/// The opposite edge stays in place, or the center if `from_center` is set
+fn resize_bounds(
+    start: Bounds,
+    handle: Handle,
+    delta: Position,
+    keep_aspect_ratio: bool,
+    from_center: bool,
+) -> Bounds {
+    let (direction_x, direction_y) = handle.direction();
+    let scale = if from_center { 2 } else { 1 };
+    let mut width = (start.width() + delta.x * direction_x * scale).max(MIN_PLAYER_SIZE);
+    let mut height = (start.height() + delta.y * direction_y * scale).max(MIN_PLAYER_SIZE);
+
+    if keep_aspect_ratio && start.width() > 0 && start.height() > 0 {
+        let ratio = start.width() as f64 / start.height() as f64;
+        let width_change = (width as f64 / start.width() as f64 - 1.0).abs();
+        let height_change = (height as f64 / start.height() as f64 - 1.0).abs();
+        // follow whichever side the handle changed the most
+        if direction_y == 0 || (direction_x != 0 && width_change >= height_change) {
+            height = ((width as f64 / ratio) as i32).max(MIN_PLAYER_SIZE);
+        } else {
+            width = ((height as f64 * ratio) as i32).max(MIN_PLAYER_SIZE);
+        }
+    }
+
+    let anchor = |direction: i32, start_min: i32, start_size: i32, size: i32| {
+        if from_center || direction == 0 {
+            start_min + (start_size - size) / 2
+        } else if direction < 0 {
+            start_min + start_size - size
+        } else {
+            start_min
+        }
+    };
+    let left = anchor(direction_x, start.left, start.width(), width);
+    let top = anchor(direction_y, start.top, start.height(), height);
+
+    Bounds {
+        left,
+        top,
+        right: left + width,
+        bottom: top + height,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DragMode {
+    Move,
+    Resize(Handle),
+}
+
+/// The state of the dragged players when a drag started
+#[derive(Clone)]
+struct DragStart {
+    mouse: Position,
+    mode: DragMode,
+    /// The bounds they started from and their height, which can be auto
+    players: Vec<(Player, Bounds, Option<i32>)>,
+}
+
+/// A file being sent to the server in chunks of [`UPLOAD_CHUNK_SIZE`]
+#[derive(Clone)]
+struct Upload {
+    name: String,
+    bytes: Arc<[u8]>,
+    received: u64,
+    /// Its serialized [`Message::StartUpload`], until fewer than [`MAX_UPLOADS_PER_SOCKET`] are running
+    queued: Option<Vec<u8>>,
+}
+
+/// Uploads started from this page, the next chunk is only sent once the server acknowledged the last one
+#[derive(Clone)]
+struct Uploads {
+    websocket: WebsocketContext,
+    uploads: RwSignal<IndexMap<u32, Upload>>,
+    next_id: StoredValue<u32>,
+}
+
+impl Uploads {
+    fn new(websocket: WebsocketContext) -> Self {
+        Self {
+            websocket,
+            uploads: RwSignal::new(IndexMap::new()),
+            next_id: StoredValue::new(0),
+        }
+    }
+
+    /// Reads a picked, dropped or pasted file and uploads it, returns false for unsupported types
+    fn upload_file(&self, file: web_sys::File, position: Position, width: i32) -> bool {
+        if MediaType::of_content_type(&file.type_()).is_none() {
+            return false;
+        }
+
+        let uploads = self.clone();
+        spawn_local(async move {
+            let Ok(file_data) = wasm_bindgen_futures::JsFuture::from(file.array_buffer()).await
+            else {
                 return;
+            };
+            let bytes = wasm_bindgen_futures::js_sys::Uint8Array::new(&file_data).to_vec();
+            uploads.start(file.name(), file.type_(), bytes, position, width);
+        });
+
+        true
+    }
+
+    fn start(
+        &self,
+        name: String,
+        content_type: String,
+        bytes: Vec<u8>,
+        position: Position,
+        width: i32,
+    ) {
+        let upload_id = self.next_id.get_value();
+        self.next_id.set_value(upload_id + 1);
+
+        let message = Message::StartUpload {
+            upload_id,
+            name: name.clone(),
+            content_type,
+            size: bytes.len() as u64,
+            position,
+            width,
+            height: None,
+        };
+        self.uploads.update(|uploads| {
+            uploads.insert(
+                upload_id,
+                Upload {
+                    name,
+                    bytes: bytes.into(),
+                    received: 0,
+                    queued: Some(bincode::serialize(&message).unwrap()),
+                },
+            );
+        });
+        self.start_queued();
+    }
+
+    /// Starts queued uploads as long as fewer than [`MAX_UPLOADS_PER_SOCKET`] are running
+    fn start_queued(&self) {
+        let messages = self
+            .uploads
+            .try_update(|uploads| {
+                let mut running = uploads
+                    .values()
+                    .filter(|upload| upload.queued.is_none())
+                    .count();
+                let mut messages = Vec::new();
+                for upload in uploads.values_mut() {
+                    if running >= MAX_UPLOADS_PER_SOCKET {
+                        break;
+                    }
+                    if let Some(message) = upload.queued.take() {
+                        messages.push(message);
+                        running += 1;
+                    }
+                }
+                messages
+            })
+            .unwrap_or_default();
+        for message in messages {
+            self.websocket.send(&message);
+        }
+    }
+
+    /// Sends the next chunk on progress, returns a notice if the server rejected an upload
+    fn handle_event(&self, event: &Event) -> Option<String> {
+        match event {
+            Event::UploadProgress {
+                upload_id,
+                received,
+            } => {
+                let upload = self
+                    .uploads
+                    .with_untracked(|uploads| uploads.get(upload_id).cloned())?;
+                let received = *received as usize;
+                if received >= upload.bytes.len() {
+                    self.uploads.update(|uploads| {
+                        uploads.shift_remove(upload_id);
+                    });
+                    self.start_queued();
+                    return None;
+                }
+
+                let end = (received + UPLOAD_CHUNK_SIZE).min(upload.bytes.len());
+                let message = Message::UploadChunk {
+                    upload_id: *upload_id,
+                    data: upload.bytes[received..end].to_vec(),
+                };
+                self.websocket.send(&bincode::serialize(&message).unwrap());
+                self.uploads.update(|uploads| {
+                    if let Some(upload) = uploads.get_mut(upload_id) {
+                        upload.received = received as u64;
+                    }
+                });
+                None
             }
+            Event::UploadRejected { upload_id, reason } => {
+                let upload = self
+                    .uploads
+                    .try_update(|uploads| uploads.shift_remove(upload_id))??;
+                self.start_queued();
+                Some(format!("Couldn't upload {}: {reason}", upload.name))
+            }
+            _ => None,
+        }
+    }
 
-            if let Some(token) = hash
-                .split("&")
-                .find_map(|s| s.strip_prefix("access_token="))
-            {
-                set_access_token(Some(token.to_string()));
-                let _ = location().set_hash("");
+    fn cancel(&self, upload_id: u32) {
+        self.uploads.update(|uploads| {
+            uploads.shift_remove(&upload_id);
+        });
+        self.websocket
+            .send(&bincode::serialize(&Message::CancelUpload { upload_id }).unwrap());
+        self.start_queued();
+    }
+}
+
+/// Times the keepalive pings, so the menu can tell a slow or silent connection from a good one
+#[derive(Clone, Copy)]
+struct Heartbeat {
+    /// When the unanswered ping was sent, in milliseconds since the unix epoch
+    sent_at: StoredValue<Option<f64>>,
+    /// How long the last answered ping took, in milliseconds
+    latency: RwSignal<Option<f64>>,
+    /// Whether the last ping was answered before the next one was due
+    is_responding: RwSignal<bool>,
+}
+
+impl Heartbeat {
+    fn new() -> Self {
+        Self {
+            sent_at: StoredValue::new(None),
+            latency: RwSignal::new(None),
+            is_responding: RwSignal::new(true),
+        }
+    }
+
+    fn ping(&self, websocket: &WebsocketContext) {
+        if self.sent_at.get_value().is_some() {
+            self.is_responding.set(false);
+        }
+        self.sent_at
+            .set_value(Some(wasm_bindgen_futures::js_sys::Date::now()));
+        websocket.send(&bincode::serialize(&Message::Ping).unwrap());
+    }
+
+    fn pong(&self) {
+        let Some(sent_at) = self.sent_at.try_update_value(Option::take).flatten() else {
+            return;
+        };
+        self.latency
+            .set(Some(wasm_bindgen_futures::js_sys::Date::now() - sent_at));
+        self.is_responding.set(true);
+    }
+
+    /// Forgets the ping sent before reconnecting, it won't be answered
+    fn reset(&self) {
+        self.sent_at.set_value(None);
+        self.is_responding.set(true);
+    }
+}
+
+/// Numbers the edits this page applies before the server has them, so an ack for an older edit
+/// doesn't undo a newer one that's still on its way
+#[derive(Clone, Copy)]
+struct Sequencer {
+    next_seq: StoredValue<u32>,
+    /// Sequence number of the last edit sent for each player
+    pending: StoredValue<HashMap<String, u32>>,
+}
+
+impl Sequencer {
+    fn new() -> Self {
+        Self {
+            next_seq: StoredValue::new(0),
+            pending: StoredValue::new(HashMap::new()),
+        }
+    }
+
+    /// Wraps an edit that was already applied locally so the server acknowledges it
+    fn sequence(&self, message: Message) -> Message {
+        let seq = self.next_seq.get_value();
+        self.next_seq.set_value(seq.wrapping_add(1));
+        self.pending.update_value(|pending| {
+            for player_name in message.edited_players() {
+                pending.insert(player_name.to_string(), seq);
             }
+        });
+
+        Message::Sequenced {
+            seq,
+            message: Box::new(message),
         }
-    });
+    }
 
+    /// Applies what the server has for the acknowledged players, except the ones edited again since
+    fn reconcile(
+        &self,
+        seq: u32,
+        events: Vec<Event>,
+        set_players: WriteSignal<IndexMap<String, Player>>,
+    ) {
+        // acks arrive in order, so every edit up to this one is handled
+        self.pending
+            .update_value(|pending| pending.retain(|_, pending_seq| *pending_seq > seq));
+        for event in events {
+            self.apply(event, set_players);
+        }
+    }
+
+    /// Applies an event, except changes of players with edits the server doesn't have yet,
+    /// their ack brings what the server ends up with
+    fn apply(&self, event: Event, set_players: WriteSignal<IndexMap<String, Player>>) {
+        match event {
+            Event::Batch(events) => {
+                for event in events {
+                    self.apply(event, set_players);
+                }
+            }
+            Event::PositionUpdated {
+                ref player_name, ..
+            }
+            | Event::SizeUpdated {
+                ref player_name, ..
+            }
+            | Event::FlipPlayerHorizontally {
+                ref player_name, ..
+            } if self
+                .pending
+                .with_value(|pending| pending.contains_key(player_name)) => {}
+            event => apply_event(event, set_players),
+        }
+    }
+}
+
+/// Logs in with the twitch access token and authorizes the websocket with the session it gets,
+/// returns whether logging in worked and the role the server gave the websocket
+pub(crate) fn use_session(
+    access_token: Signal<Option<String>>,
+) -> (ReadSignal<bool>, ReadSignal<Option<Role>>) {
     let websocket = expect_context::<WebsocketContext>();
+    let (authorized, set_authorized) = signal(false);
+    let (role, set_role) = signal(None::<Role>);
 
+    // twitch is only asked when logging in, the websocket is authorized with the session
+    let (session, set_session) = signal(None::<String>);
     Effect::new(move |_| {
         if let Some(access_token) = access_token() {
             spawn_local(async move {
-                // authorize on client
-                let authorized = is_authorized(access_token.clone()).await.is_ok_and(|is| is);
-                if authorized {
-                    tracing::debug!("authorized");
-                    set_authorized(true);
-                } else {
-                    tracing::debug!("unauthorized");
-                    set_authorized(false);
+                match log_in(access_token).await {
+                    Ok(new_session) => {
+                        tracing::debug!("authorized");
+                        set_session(Some(new_session));
+                        set_authorized(true);
+                    }
+                    Err(e) => {
+                        tracing::debug!("unauthorized: {e}");
+                        set_session(None);
+                        set_authorized(false);
+                    }
                 }
             });
         }
@@ -89,9 +591,9 @@ pub fn ControlPage() -> impl IntoView {
     {
         let websocket = websocket.clone();
         Effect::new(move |_| {
-            if let Some(access_token) = access_token() {
+            if let Some(session) = session() {
                 // authorize on server
-                let message = Message::Authorize(access_token);
+                let message = Message::Authorize(session);
                 match websocket.ready_state.get() {
                     ConnectionReadyState::Open => {
                         websocket.send(&bincode::serialize(&message).unwrap());
@@ -104,11 +606,55 @@ pub fn ControlPage() -> impl IntoView {
     {
         let websocket = websocket.clone();
         Effect::new(move |_| {
-            if let ConnectionReadyState::Closed = websocket.ready_state.get() {
+            if let ConnectionReadyState::Closed = websocket.ready_state.get()
+                && websocket.kicked.with_untracked(Option::is_none)
+            {
                 websocket.open();
             }
         });
     }
+    Effect::new(move |_| {
+        if let Some(message) = websocket.message.get()
+            && let Ok(Event::Authorized { role }) = bincode::deserialize::<Event>(&message)
+        {
+            set_role(Some(role));
+        }
+    });
+
+    (authorized, role)
+}
+
+#[component]
+pub fn ControlPage() -> impl IntoView {
+    let (base_url, set_base_url) = signal(String::new());
+    Effect::new(move |_| {
+        let location = window().location();
+
+        set_base_url(location.origin().unwrap());
+    });
+
+    // auth (I guess)
+    let (access_token, set_access_token, _) =
+        use_local_storage::<Option<String>, JsonSerdeCodec>("access_token");
+
+    Effect::new(move |_| {
+        if let Some(hash) = location_hash() {
+            if hash.is_empty() {
+                return;
+            }
+
+            if let Some(token) = hash
+                .split("&")
+                .find_map(|s| s.strip_prefix("access_token="))
+            {
+                set_access_token(Some(token.to_string()));
+                let _ = location().set_hash("");
+            }
+        }
+    });
+
+    let websocket = expect_context::<WebsocketContext>();
+    let (authorized, role) = use_session(access_token);
 
     let (show_menu, set_show_menu) = signal(true);
 
@@ -119,6 +665,8 @@ pub fn ControlPage() -> impl IntoView {
     let (canvas_move_click, set_canvas_move_click) = signal(false);
     let (canvas_position, set_canvas_position) = signal(Position { x: 0, y: 0 });
     let (canvas_zoom, set_canvas_zoom) = signal(1.0f64);
+    // last known mouse position in screen coordinates, pasted media is placed here
+    let (mouse_position, set_mouse_position) = signal(Position { x: 0, y: 0 });
     let (space_pressed, set_space_pressed) = signal(false);
     let (ctrl_pressed, set_ctrl_pressed) = signal(false);
 
@@ -182,6 +730,7 @@ pub fn ControlPage() -> impl IntoView {
     });
 
     let _ = use_event_listener(use_window(), leptos::ev::mousemove, move |event| {
+        set_mouse_position(Position::new(event.client_x(), event.client_y()));
         if canvas_move_click() {
             event.prevent_default();
             set_canvas_position.update(|current_pos| {
@@ -219,182 +768,1030 @@ pub fn ControlPage() -> impl IntoView {
     };
 
     let (players, set_players) = signal(IndexMap::<String, Player>::new());
-
-    view! {
-        <Show when=move || authorized() fallback=fallback_view>
-            <div style:cursor=move || {
-                if canvas_move_click() { "grabbing" } else if space_pressed() { "grab" } else { "" }
-            }>
-
-                {move || {
-                    if show_menu() {
-                        view! { <Menu players canvas_position canvas_zoom/> }.into_any()
-                    } else {
-                        view! {}.into_any()
-                    }
-                }}
-                <Players players set_players canvas_position canvas_zoom ctrl_pressed authorized/>
-            </div>
-        </Show>
-    }
-}
-
-#[component]
-fn Players(
-    players: ReadSignal<IndexMap<String, Player>>,
-    set_players: WriteSignal<IndexMap<String, Player>>,
-    canvas_position: ReadSignal<Position>,
-    canvas_zoom: ReadSignal<f64>,
-    ctrl_pressed: ReadSignal<bool>,
-    authorized: ReadSignal<bool>,
-) -> impl IntoView {
-    // let owner = leptos::Owner::current().expect("there should be an owner");
-    let (move_click, set_move_click) = signal(false);
-    let (resize_click, set_resize_click) = signal(false);
-
-    let websocket = expect_context::<WebsocketContext>();
+    let (screen_size, set_screen_size) = signal(ScreenSize::default());
+    let (snap_settings, set_snap_settings) = signal(SnapSettings::default());
+    let (show_shortcuts, set_show_shortcuts) = signal(false);
+    // last problem reported by the server, shown in the menu
+    let (notice, set_notice) = signal(None::<String>);
+
+    let uploads = Uploads::new(websocket.clone());
+    provide_context(uploads.clone());
+    let sequencer = Sequencer::new();
+    provide_context(sequencer);
+    let heartbeat = Heartbeat::new();
+    provide_context(heartbeat);
     {
         let websocket = websocket.clone();
-        use_interval_fn(
-            move || {
-                websocket.send(&bincode::serialize(&Message::Ping).unwrap());
-            },
-            5000,
-        );
+        Effect::new(move |_| {
+            if let ConnectionReadyState::Open = websocket.ready_state.get() {
+                heartbeat.reset();
+            }
+        });
     }
 
     {
         let websocket = websocket.clone();
+        let uploads = uploads.clone();
         Effect::new(move |_| {
-            if let ConnectionReadyState::Open = websocket.ready_state.get() {
-                websocket.send(&bincode::serialize(&Message::GetAllPlayers).unwrap());
+            let Some(Ok(event)) = websocket
+                .message
+                .get()
+                .map(|message| bincode::deserialize::<Event>(&message))
+            else {
+                return;
+            };
+            if let Event::MediaRejected { name, reason } = &event {
+                // adding or purging it
+                set_notice(Some(format!("{name}: {reason}")));
+            } else if let Some(notice) = uploads.handle_event(&event) {
+                set_notice(Some(notice));
             }
         });
     }
-
     {
         let websocket = websocket.clone();
         Effect::new(move |_| {
-            if let ConnectionReadyState::Open = websocket.ready_state.get() {
-                handle_websocket_message(websocket.clone(), set_players.clone());
+            if let Some(reason) = websocket.kicked.get() {
+                set_notice(Some(format!("{reason}, reload the page to reconnect")));
             }
         });
     }
 
-    let send_set_position = {
+    let to_overlay_position = move |position: Position| {
+        Position::new(
+            (position.x as f64 / canvas_zoom.get_untracked()) as i32
+                - canvas_position.get_untracked().x,
+            (position.y as f64 / canvas_zoom.get_untracked()) as i32
+                - canvas_position.get_untracked().y,
+        )
+    };
+
+    // everyone else on the control page, by socket id
+    let (others, set_others) = signal(IndexMap::<u32, Presence>::new());
+    // players someone else is dragging or resizing, by name
+    let (leases, set_leases) = signal(IndexMap::<String, Lease>::new());
+    {
         let websocket = websocket.clone();
-        move |player_name: String, x: i32, y: i32| {
-            let message = Message::SetPosition {
-                player_name,
-                new_position: Position { x, y },
+        Effect::new(move |_| {
+            let Some(message) = websocket.message.get() else {
+                return;
             };
-            if let ConnectionReadyState::Open = websocket.ready_state.get() {
-                if authorized() {
-                    websocket.send(&bincode::serialize(&message).unwrap());
-                }
+            match bincode::deserialize::<Event>(&message) {
+                Ok(Event::Presence(everyone_else)) => set_others(
+                    everyone_else
+                        .into_iter()
+                        .map(|presence| (presence.socket_id, presence))
+                        .collect(),
+                ),
+                Ok(Event::PresenceUpdated(presence)) => set_others.update(|others| {
+                    others.insert(presence.socket_id, presence);
+                }),
+                Ok(Event::Leases(held_by_others)) => set_leases(held_by_others),
+                Ok(Event::Ack { seq, events }) => sequencer.reconcile(seq, events, set_players),
+                Ok(Event::Pong) => heartbeat.pong(),
+                _ => {}
             }
-        }
-    };
+        });
+    }
 
-    let send_set_size = move |player_name: String, width: i32, height: Option<i32>| {
-        let message = Message::SetSize {
-            player_name,
-            width,
-            height,
-        };
-        if let ConnectionReadyState::Open = websocket.ready_state.get() {
-            if authorized() {
+    // the selection and mouse position are sent at most every PRESENCE_INTERVAL_MS, not on every change
+    let (cursor, set_cursor) = signal(None::<Position>);
+    let presence_changed = StoredValue::new(false);
+    let _ = use_event_listener(use_window(), leptos::ev::mousemove, move |event| {
+        set_cursor(Some(to_overlay_position(Position::new(
+            event.client_x(),
+            event.client_y(),
+        ))));
+    });
+    let _ = use_event_listener(
+        document().document_element(),
+        leptos::ev::mouseleave,
+        move |_| set_cursor(None),
+    );
+    Effect::new(move |_| {
+        players.with(|players| {
+            players
+                .values()
+                .for_each(|player| player.is_selected.track())
+        });
+        cursor.track();
+        presence_changed.set_value(true);
+    });
+    {
+        let websocket = websocket.clone();
+        use_interval_fn(
+            move || {
+                if !presence_changed.get_value() || !authorized.get_untracked() {
+                    return;
+                }
+                presence_changed.set_value(false);
+                let message = Message::UpdatePresence {
+                    selection: players
+                        .get_untracked()
+                        .values()
+                        .filter(|player| player.is_selected.get_untracked())
+                        .map(|player| player.name.get_untracked())
+                        .collect(),
+                    cursor: cursor.get_untracked(),
+                };
                 websocket.send(&bincode::serialize(&message).unwrap());
+            },
+            PRESENCE_INTERVAL_MS,
+        );
+    }
+
+    // adds dropped or pasted media at `position`, given in screen coordinates
+    let add_media_at = {
+        let websocket = websocket.clone();
+        move |name: String, data: String, media_type: MediaType, position: Position| {
+            if !authorized.get_untracked() {
+                return;
+            }
+            let height = match media_type {
+                MediaType::Text => Some(200),
+                MediaType::Image | MediaType::Video => None,
+            };
+            let message = Message::NewMedia {
+                name,
+                data,
+                media_type,
+                position: to_overlay_position(position),
+                width: 200,
+                height,
+            };
+            let message = sequencer.sequence(message);
+            websocket.send(&bincode::serialize(&message).unwrap());
+        }
+    };
+
+    // media links are fetched by the server, anything else becomes text
+    let add_text_at = {
+        let add_media_at = add_media_at.clone();
+        let websocket = websocket.clone();
+        move |text: String, position: Position| {
+            let text = text.trim().to_string();
+            if text.is_empty() {
+                return;
+            }
+            if MediaType::of_url(&text).is_none() {
+                add_media_at(text.clone(), text, MediaType::Text, position);
+                return;
+            }
+            if !authorized.get_untracked() {
+                return;
             }
+            let message = sequencer.sequence(Message::NewMediaFromUrl {
+                url: text,
+                position: to_overlay_position(position),
+                width: 200,
+                height: None,
+            });
+            websocket.send(&bincode::serialize(&message).unwrap());
         }
     };
 
-    let (prev_mouse_pos, set_prev_mouse_pos) = signal(Position { x: 0, y: 0 });
+    let add_files_at = {
+        let uploads = uploads.clone();
+        move |files: web_sys::FileList, position: Position| {
+            if !authorized.get_untracked() {
+                return false;
+            }
+            let mut added = false;
+            for i in 0..files.length() {
+                if let Some(file) = files.item(i) {
+                    added |= uploads.upload_file(file, to_overlay_position(position), 200);
+                }
+            }
+            added
+        }
+    };
 
-    let move_mouse = move |width: RwSignal<i32>,
-                           height: RwSignal<Option<i32>>,
-                           position: RwSignal<Position>,
-                           name: RwSignal<String>,
-                           event: leptos::ev::MouseEvent| {
+    let _ = use_event_listener(use_window(), leptos::ev::dragover, move |event| {
+        // required for the browser to allow dropping
         event.prevent_default();
+    });
 
-        let send_set_position = send_set_position.clone();
-        let send_set_size = send_set_size.clone();
-        event.prevent_default();
-        if move_click() {
-            position.update(|pos| {
-                let movement_x = event.x() - prev_mouse_pos().x;
-                let movement_y = event.y() - prev_mouse_pos().y;
-                pos.x += ((movement_x as f64) / canvas_zoom()) as i32;
-                pos.y += ((movement_y as f64) / canvas_zoom()) as i32;
-                send_set_position(name(), pos.x, pos.y);
-                set_prev_mouse_pos(Position {
-                    x: event.x(),
-                    y: event.y(),
+    let _ = use_event_listener(use_window(), leptos::ev::drop, {
+        let add_files_at = add_files_at.clone();
+        let add_text_at = add_text_at.clone();
+        move |event| {
+            event.prevent_default();
+            let Some(data_transfer) = event.data_transfer() else {
+                return;
+            };
+            let position = Position::new(event.client_x(), event.client_y());
+
+            if let Some(files) = data_transfer.files()
+                && add_files_at(files, position)
+            {
+                return;
+            }
+
+            // links dragged from other pages come as a uri list, where lines starting with # are comments
+            let link = data_transfer
+                .get_data("text/uri-list")
+                .ok()
+                .and_then(|uris| {
+                    uris.lines()
+                        .find(|line| !line.starts_with('#') && !line.trim().is_empty())
+                        .map(|line| line.to_string())
                 });
-            });
-        } else if resize_click() {
-            width.update(|current_width| {
-                *current_width =
-                    (*current_width as f64 + (event.movement_x() as f64 / canvas_zoom())) as i32;
-            });
-            height.update(|current_height| {
-                if ctrl_pressed() {
-                    // None means this should keep the aspect ratio of the player and set the height to auto
-                    *current_height = None;
-                } else {
-                    *current_height = Some(
-                        (current_height.unwrap_or(width()) as f64
-                            + (event.movement_y() as f64 / canvas_zoom()))
-                            as i32,
-                    );
+            if let Some(text) = link.or_else(|| data_transfer.get_data("text/plain").ok()) {
+                add_text_at(text, position);
+            }
+        }
+    });
+
+    let _ = use_event_listener(use_window(), leptos::ev::paste, move |event| {
+        // pasting into text fields should work as usual
+        if event
+            .target()
+            .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+            .is_some_and(|element| matches!(element.tag_name().as_str(), "INPUT" | "TEXTAREA"))
+        {
+            return;
+        }
+        let Some(clipboard) = event.clipboard_data() else {
+            return;
+        };
+        event.prevent_default();
+        let position = mouse_position.get_untracked();
+
+        if let Some(files) = clipboard.files()
+            && add_files_at(files, position)
+        {
+            return;
+        }
+        if let Ok(text) = clipboard.get_data("text/plain") {
+            add_text_at(text, position);
+        }
+    });
+
+    let _ = use_event_listener(use_window(), leptos::ev::keydown, move |event| {
+        // don't steal keys from text fields
+        if event
+            .target()
+            .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+            .is_some_and(|element| matches!(element.tag_name().as_str(), "INPUT" | "TEXTAREA"))
+        {
+            return;
+        }
+
+        if event.key() == "?" {
+            set_show_shortcuts.update(|show| *show = !*show);
+            return;
+        }
+
+        // in the order they are displayed, so moving several players keeps their relative order
+        let selected = players
+            .get_untracked()
+            .into_values()
+            .filter(|player| player.is_selected.get_untracked())
+            .collect::<Vec<_>>();
+        if selected.is_empty() {
+            return;
+        }
+
+        let step = if event.shift_key() { 10 } else { 1 };
+        let nudge = |x: i32, y: i32| {
+            selected
+                .iter()
+                .map(|player| {
+                    let position = player.position.get_untracked();
+                    Message::SetPosition {
+                        player_name: player.name.get_untracked(),
+                        new_position: Position::new(position.x + x, position.y + y),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let messages = match (event.key().as_str(), event.ctrl_key()) {
+            ("ArrowLeft", _) => nudge(-step, 0),
+            ("ArrowRight", _) => nudge(step, 0),
+            ("ArrowUp", _) => nudge(0, -step),
+            ("ArrowDown", _) => nudge(0, step),
+            ("Delete", _) => selected
+                .iter()
+                .map(|player| Message::DeletePlayer {
+                    player_name: player.name.get_untracked(),
+                })
+                .collect(),
+            ("d" | "D", true) => selected
+                .iter()
+                .map(|player| Message::DuplicatePlayer {
+                    player_name: player.name.get_untracked(),
+                    offset: DUPLICATE_OFFSET,
+                })
+                .collect(),
+            ("h" | "H", false) => selected
+                .iter()
+                .map(|player| Message::FlipPlayerHorizontally {
+                    player_name: player.name.get_untracked(),
+                    is_flipped: !player.horizontal_flip.get_untracked(),
+                })
+                .collect(),
+            ("PageUp", _) => selected
+                .iter()
+                .map(|player| Message::MovePlayerUp {
+                    player_name: player.name.get_untracked(),
+                })
+                .collect(),
+            ("PageDown", _) => selected
+                .iter()
+                .rev()
+                .map(|player| Message::MovePlayerDown {
+                    player_name: player.name.get_untracked(),
+                })
+                .collect(),
+            _ => return,
+        };
+
+        event.prevent_default();
+        let own_role = role.get_untracked();
+        let is_allowed = |message: &Message| {
+            message
+                .required_role()
+                .is_none_or(|required| own_role.is_some_and(|role| role >= required))
+        };
+        if !authorized.get_untracked() || !messages.iter().all(is_allowed) {
+            return;
+        }
+
+        // moves and flips show right away, the rest waits for the server
+        let player = |player_name: &String| {
+            selected
+                .iter()
+                .find(|player| player.name.get_untracked() == *player_name)
+        };
+        for message in &messages {
+            match message {
+                Message::SetPosition {
+                    player_name,
+                    new_position,
+                } => {
+                    if let Some(player) = player(player_name) {
+                        player.position.set(*new_position);
+                    }
                 }
+                Message::FlipPlayerHorizontally {
+                    player_name,
+                    is_flipped,
+                } => {
+                    if let Some(player) = player(player_name) {
+                        player.horizontal_flip.set(*is_flipped);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let message = sequencer.sequence(Message::Batch(messages));
+        websocket.send(&bincode::serialize(&message).unwrap());
+    });
+
+    view! {
+        <Show when=move || authorized() fallback=fallback_view>
+            <div style:cursor=move || {
+                if canvas_move_click() { "grabbing" } else if space_pressed() { "grab" } else { "" }
+            }>
+
+                {move || {
+                    if show_menu() {
+                        view! {
+                            <Menu
+                                players
+                                notice
+                                set_notice
+                                canvas_position
+                                canvas_zoom
+                                screen_size
+                                set_screen_size
+                                snap_settings
+                                set_snap_settings
+                                role
+                                others
+                                access_token
+                            />
+                        }
+                            .into_any()
+                    } else {
+                        view! {}.into_any()
+                    }
+                }}
+                <Show when=move || show_shortcuts()>
+                    <Shortcuts set_show_shortcuts/>
+                </Show>
+                <Players
+                    players
+                    set_players
+                    canvas_position
+                    canvas_zoom
+                    ctrl_pressed
+                    space_pressed
+                    authorized
+                    screen_size
+                    snap_settings
+                    others
+                    leases
+                />
+            </div>
+        </Show>
+    }
+}
+
+const SHORTCUTS: &[(&str, &str)] = &[
+    ("?", "Show or hide this help"),
+    ("Escape", "Show or hide the menu"),
+    ("Space + drag / middle mouse", "Pan the canvas"),
+    ("Scroll", "Zoom"),
+    ("Shift + click", "Add to the selection"),
+    ("Drag on empty space", "Select media in a box"),
+    ("Right mouse drag", "Resize the selection"),
+    ("Shift while resizing", "Keep the aspect ratio"),
+    ("Alt while resizing", "Resize from the center"),
+    ("Ctrl while resizing", "Automatic height"),
+    ("Arrow keys", "Nudge the selection by 1px"),
+    ("Shift + arrow keys", "Nudge the selection by 10px"),
+    ("Delete", "Remove the selection"),
+    ("Ctrl + D", "Duplicate the selection"),
+    ("H", "Flip the selection horizontally"),
+    ("Page Up / Page Down", "Move the selection up or down"),
+];
+
+#[component]
+fn Shortcuts(set_show_shortcuts: WriteSignal<bool>) -> impl IntoView {
+    view! {
+        <div
+            on:mousedown=move |event| event.stop_propagation()
+            style="position: fixed; left: 50%; top: 50%; transform: translate(-50%, -50%); z-index: 6000; background: #535594; padding: 1rem; border-radius: 0.5rem;"
+        >
+            <div style="display: flex; justify-content: space-between; align-items: center; gap: 2rem;">
+                <h2 style="margin: 0;">"Keyboard shortcuts"</h2>
+                <button on:click=move |_| set_show_shortcuts(false) title="Close">
+                    "✕"
+                </button>
+            </div>
+            <table style="text-align: left; border-spacing: 1rem 0.25rem;">
+                {SHORTCUTS
+                    .iter()
+                    .map(|(keys, action)| {
+                        view! {
+                            <tr>
+                                <td>
+                                    <kbd>{*keys}</kbd>
+                                </td>
+                                <td>{*action}</td>
+                            </tr>
+                        }
+                    })
+                    .collect_view()}
+            </table>
+        </div>
+    }
+}
+
+#[component]
+fn Players(
+    players: ReadSignal<IndexMap<String, Player>>,
+    set_players: WriteSignal<IndexMap<String, Player>>,
+    canvas_position: ReadSignal<Position>,
+    canvas_zoom: ReadSignal<f64>,
+    ctrl_pressed: ReadSignal<bool>,
+    space_pressed: ReadSignal<bool>,
+    authorized: ReadSignal<bool>,
+    screen_size: ReadSignal<ScreenSize>,
+    snap_settings: ReadSignal<SnapSettings>,
+    others: ReadSignal<IndexMap<u32, Presence>>,
+    leases: ReadSignal<IndexMap<String, Lease>>,
+) -> impl IntoView {
+    // let owner = leptos::Owner::current().expect("there should be an owner");
+    let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
+    let heartbeat = expect_context::<Heartbeat>();
+    // the revision of the players this page has, so reconnecting only needs what changed since
+    let revision = StoredValue::new(None::<u64>);
+    {
+        let websocket = websocket.clone();
+        use_interval_fn(
+            move || {
+                heartbeat.ping(&websocket);
+            },
+            5000,
+        );
+    }
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            if let ConnectionReadyState::Open = websocket.ready_state.get() {
+                request_players(&websocket, revision);
+            }
+        });
+    }
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            if let ConnectionReadyState::Open = websocket.ready_state.get()
+                && let Some(message) = websocket.message.get()
+            {
+                match bincode::deserialize::<Event>(&message) {
+                    Ok(Event::Revision(new_revision)) => revision.set_value(Some(new_revision)),
+                    // this page's own edits come back too
+                    Ok(event) => sequencer.apply(event, set_players),
+                    Err(e) => tracing::warn!("couldn't decode event: {e}"),
+                }
+            }
+        });
+    }
+
+    let send_lease_message = {
+        let websocket = websocket.clone();
+        move |message: Message| {
+            if authorized.get_untracked() {
+                websocket.send(&bincode::serialize(&message).unwrap());
+            }
+        }
+    };
+
+    let send_batch = {
+        let websocket = websocket.clone();
+        move |messages: Vec<Message>| {
+            if messages.is_empty() {
+                return;
+            }
+            if let ConnectionReadyState::Open = websocket.ready_state.get() {
+                if authorized() {
+                    let message = sequencer.sequence(Message::Batch(messages));
+                    websocket.send(&bincode::serialize(&message).unwrap());
+                }
+            }
+        }
+    };
+
+    // drag updates are sent at most once per animation frame, only the latest one of a frame counts
+    let queued_drag_update = StoredValue::new(None::<Vec<Message>>);
+    let send_drag_update = move |messages: Vec<Message>| {
+        if messages.is_empty() {
+            return;
+        }
+        let is_frame_requested = queued_drag_update.with_value(Option::is_some);
+        queued_drag_update.set_value(Some(messages));
+        if is_frame_requested {
+            return;
+        }
+
+        let websocket = websocket.clone();
+        request_animation_frame(move || {
+            let Some(messages) = queued_drag_update.try_update_value(Option::take).flatten() else {
+                return;
+            };
+            if let ConnectionReadyState::Open = websocket.ready_state.get_untracked()
+                && authorized.get_untracked()
+            {
+                websocket.send(&bincode::serialize(&Message::DragUpdate(messages)).unwrap());
+            }
+        });
+    };
+
+    let (drag_start, set_drag_start) = signal(None::<DragStart>);
+
+    // players someone else holds are left out, the server wouldn't accept changing them anyway
+    let start_drag = Callback::new({
+        let send_lease_message = send_lease_message.clone();
+        move |(mouse, mode, dragged): (Position, DragMode, Vec<Player>)| {
+            let dragged = dragged
+                .into_iter()
+                .filter(|player| {
+                    leases
+                        .with_untracked(|leases| !leases.contains_key(&player.name.get_untracked()))
+                })
+                .collect::<Vec<_>>();
+            if dragged.is_empty() {
+                return;
+            }
+            send_lease_message(Message::AcquireLeases {
+                player_names: dragged
+                    .iter()
+                    .map(|player| player.name.get_untracked())
+                    .collect(),
             });
-            send_set_size(name(), width.get_untracked(), height.get_untracked());
+            set_drag_start(Some(DragStart {
+                mouse,
+                mode,
+                players: dragged
+                    .into_iter()
+                    .map(|player| {
+                        let bounds = Bounds::of_player(&player);
+                        let height = player.height.get_untracked();
+                        (player, bounds, height)
+                    })
+                    .collect(),
+            }));
+        }
+    });
+
+    // someone else got a lease first, stop dragging what they hold
+    Effect::new(move |_| {
+        let held_by_others = leases.get();
+        if drag_start.with_untracked(Option::is_none) {
+            return;
         }
+        set_drag_start.update(|start| {
+            if let Some(start) = start {
+                start.players.retain(|(player, ..)| {
+                    !held_by_others.contains_key(&player.name.get_untracked())
+                });
+            }
+        });
+    });
+    let (guides, set_guides) = signal(Vec::<Guide>::new());
+    // start and end corners of the rubber band selection, in screen coordinates
+    let (selection_box, set_selection_box) = signal(None::<(Position, Position)>);
+
+    let selected_players = move || {
+        players
+            .get_untracked()
+            .into_values()
+            .filter(|player| player.is_selected.get_untracked())
+            .collect::<Vec<_>>()
     };
 
+    // edges and center lines the selection can snap to, horizontally and vertically
+    let snap_targets = move || {
+        let settings = snap_settings.get_untracked();
+        let mut xs = Vec::new();
+        let mut ys = Vec::new();
+
+        if settings.to_screen {
+            let screen = screen_size.get_untracked();
+            xs.extend([0, screen.width / 2, screen.width]);
+            ys.extend([0, screen.height / 2, screen.height]);
+        }
+
+        if settings.to_players {
+            for player in players.get_untracked().values() {
+                if player.is_selected.get_untracked() {
+                    continue;
+                }
+                let bounds = Bounds::of_player(player);
+                xs.extend([bounds.left, bounds.center_x(), bounds.right]);
+                ys.extend([bounds.top, bounds.center_y(), bounds.bottom]);
+            }
+        }
+
+        (xs, ys)
+    };
+
+    let _ = use_event_listener(use_window(), leptos::ev::mousedown, move |event| {
+        if event.button() != 0 || space_pressed() {
+            return;
+        }
+        if !event.shift_key() {
+            players
+                .get_untracked()
+                .values()
+                .for_each(|player| player.is_selected.set(false));
+        }
+        let mouse_pos = Position::new(event.x(), event.y());
+        set_selection_box(Some((mouse_pos, mouse_pos)));
+    });
+
+    let _ = use_event_listener(use_window(), leptos::ev::mousemove, {
+        let send_drag_update = send_drag_update.clone();
+        move |event| {
+            if selection_box.get_untracked().is_some() {
+                set_selection_box.update(|selection| {
+                    if let Some((_, end)) = selection {
+                        *end = Position::new(event.x(), event.y());
+                    }
+                });
+                return;
+            }
+
+            let Some(start) = drag_start.get_untracked() else {
+                return;
+            };
+            event.prevent_default();
+
+            let mut delta_x = ((event.x() - start.mouse.x) as f64 / canvas_zoom()) as i32;
+            let mut delta_y = ((event.y() - start.mouse.y) as f64 / canvas_zoom()) as i32;
+
+            let messages = match start.mode {
+                DragMode::Move => {
+                    let Some(start_bounds) = start
+                        .players
+                        .iter()
+                        .map(|(_, bounds, _)| *bounds)
+                        .reduce(Bounds::union)
+                    else {
+                        return;
+                    };
+
+                    let threshold = (SNAP_DISTANCE / canvas_zoom()) as i32;
+                    let grid_size = snap_settings.get_untracked().grid_size;
+                    let (xs, ys) = snap_targets();
+                    let mut new_guides = Vec::new();
+
+                    let left = start_bounds.left + delta_x;
+                    let top = start_bounds.top + delta_y;
+                    let edges_x = [
+                        left,
+                        left + start_bounds.width() / 2,
+                        left + start_bounds.width(),
+                    ];
+                    let edges_y = [
+                        top,
+                        top + start_bounds.height() / 2,
+                        top + start_bounds.height(),
+                    ];
+
+                    if let Some((offset, target)) = snap_offset(edges_x, &xs, threshold) {
+                        delta_x += offset;
+                        new_guides.push(Guide::Vertical(target));
+                    } else if grid_size > 0 {
+                        delta_x +=
+                            (left as f64 / grid_size as f64).round() as i32 * grid_size - left;
+                    }
+
+                    if let Some((offset, target)) = snap_offset(edges_y, &ys, threshold) {
+                        delta_y += offset;
+                        new_guides.push(Guide::Horizontal(target));
+                    } else if grid_size > 0 {
+                        delta_y += (top as f64 / grid_size as f64).round() as i32 * grid_size - top;
+                    }
+
+                    set_guides(new_guides);
+
+                    start
+                        .players
+                        .iter()
+                        .map(|(player, bounds, _)| {
+                            player
+                                .position
+                                .set(Position::new(bounds.left + delta_x, bounds.top + delta_y));
+                            Message::SetPosition {
+                                player_name: player.name.get_untracked(),
+                                new_position: player.position.get_untracked(),
+                            }
+                        })
+                        .collect()
+                }
+                DragMode::Resize(handle) => start
+                    .players
+                    .iter()
+                    .flat_map(|(player, start_bounds, start_height)| {
+                        let bounds = resize_bounds(
+                            *start_bounds,
+                            handle,
+                            Position::new(delta_x, delta_y),
+                            event.shift_key(),
+                            event.alt_key(),
+                        );
+
+                        player.position.set(Position::new(bounds.left, bounds.top));
+                        player.width.set(bounds.width());
+                        // an auto height follows the width already when the aspect ratio is kept
+                        let keeps_auto_height = start_height.is_none() && event.shift_key();
+                        if ctrl_pressed() || keeps_auto_height {
+                            // None means this should keep the aspect ratio of the player and set the height to auto
+                            player.height.set(None);
+                        } else {
+                            player.height.set(Some(bounds.height()));
+                        }
+
+                        [
+                            Message::SetPosition {
+                                player_name: player.name.get_untracked(),
+                                new_position: player.position.get_untracked(),
+                            },
+                            Message::SetSize {
+                                player_name: player.name.get_untracked(),
+                                width: player.width.get_untracked(),
+                                height: player.height.get_untracked(),
+                            },
+                        ]
+                    })
+                    .collect(),
+            };
+
+            send_drag_update(messages);
+        }
+    });
+
+    let _ = use_event_listener(use_window(), leptos::ev::mouseup, move |_event| {
+        // the final values replace whatever drag update is still waiting for its frame
+        if let Some(start) = drag_start.get_untracked() {
+            queued_drag_update.set_value(None);
+            let messages = start
+                .players
+                .iter()
+                .flat_map(|(player, bounds, height)| {
+                    let mut messages = Vec::new();
+                    if player.position.get_untracked() != Position::new(bounds.left, bounds.top) {
+                        messages.push(Message::SetPosition {
+                            player_name: player.name.get_untracked(),
+                            new_position: player.position.get_untracked(),
+                        });
+                    }
+                    if (player.width.get_untracked(), player.height.get_untracked())
+                        != (bounds.width(), *height)
+                    {
+                        messages.push(Message::SetSize {
+                            player_name: player.name.get_untracked(),
+                            width: player.width.get_untracked(),
+                            height: player.height.get_untracked(),
+                        });
+                    }
+                    messages
+                })
+                .collect();
+            send_batch(messages);
+            send_lease_message(Message::ReleaseLeases);
+        }
+        set_drag_start(None);
+        set_guides(Vec::new());
+
+        let Some((start, end)) = selection_box.get_untracked() else {
+            return;
+        };
+        set_selection_box(None);
+
+        // convert the screen space box into overlay coordinates
+        let to_overlay = |pos: Position| {
+            Position::new(
+                (pos.x as f64 / canvas_zoom()) as i32 - canvas_position().x,
+                (pos.y as f64 / canvas_zoom()) as i32 - canvas_position().y,
+            )
+        };
+        let (start, end) = (to_overlay(start), to_overlay(end));
+        let selection = Bounds {
+            left: start.x.min(end.x),
+            top: start.y.min(end.y),
+            right: start.x.max(end.x),
+            bottom: start.y.max(end.y),
+        };
+
+        for player in players.get_untracked().values() {
+            if Bounds::of_player(player).intersects(&selection) {
+                player.is_selected.set(true);
+            }
+        }
+    });
+
     view! {
+        <For
+            each=move || guides()
+            key=|guide| *guide
+            children=move |guide| {
+                let placement = move || match guide {
+                    Guide::Vertical(x) => {
+                        format!(
+                            "left: {}px; top: 0; width: 1px; height: 100vh;",
+                            (x + canvas_position().x) as f64 * canvas_zoom(),
+                        )
+                    }
+                    Guide::Horizontal(y) => {
+                        format!(
+                            "left: 0; top: {}px; width: 100vw; height: 1px;",
+                            (y + canvas_position().y) as f64 * canvas_zoom(),
+                        )
+                    }
+                };
+                view! {
+                    <div style=move || {
+                        format!(
+                            "position: absolute; z-index: 4000; pointer-events: none; background: magenta; {}",
+                            placement(),
+                        )
+                    }></div>
+                }
+            }
+        />
+        <Show when=move || selection_box().is_some()>
+            <div
+                style="position: absolute; z-index: 4000; pointer-events: none; border: 1px dashed white; background: rgba(83, 85, 148, 0.3);"
+                style:left=move || {
+                    selection_box()
+                        .map(|(start, end)| format!("{}px", start.x.min(end.x)))
+                        .unwrap_or_default()
+                }
+
+                style:top=move || {
+                    selection_box()
+                        .map(|(start, end)| format!("{}px", start.y.min(end.y)))
+                        .unwrap_or_default()
+                }
+
+                style:width=move || {
+                    selection_box()
+                        .map(|(start, end)| format!("{}px", (start.x - end.x).abs()))
+                        .unwrap_or_default()
+                }
+
+                style:height=move || {
+                    selection_box()
+                        .map(|(start, end)| format!("{}px", (start.y - end.y).abs()))
+                        .unwrap_or_default()
+                }
+            ></div>
+        </Show>
+        <For
+            each=move || {
+                others().into_values().filter(|presence| presence.cursor.is_some()).collect::<Vec<_>>()
+            }
+            key=|presence| presence.socket_id
+            children=move |presence: Presence| {
+                let socket_id = presence.socket_id;
+                let cursor = move || {
+                    others.with(|others| others.get(&socket_id).and_then(|presence| presence.cursor))
+                };
+                view! {
+                    <div
+                        style="position: absolute; z-index: 4000; pointer-events: none; display: flex; align-items: flex-start;"
+                        style:left=move || {
+                            cursor()
+                                .map(|cursor| {
+                                    format!(
+                                        "{}px",
+                                        (cursor.x + canvas_position().x) as f64 * canvas_zoom(),
+                                    )
+                                })
+                                .unwrap_or_default()
+                        }
+
+                        style:top=move || {
+                            cursor()
+                                .map(|cursor| {
+                                    format!(
+                                        "{}px",
+                                        (cursor.y + canvas_position().y) as f64 * canvas_zoom(),
+                                    )
+                                })
+                                .unwrap_or_default()
+                        }
+                    >
+                        <div
+                            style="width: 10px; height: 10px; border-radius: 50%; transform: translate(-50%, -50%);"
+                            style:background=presence.color.clone()
+                        ></div>
+                        <small style="padding: 0 0.25rem; color: black;" style:background=presence.color>
+                            {presence.name}
+                        </small>
+                    </div>
+                }
+            }
+        />
         <For
             each=move || players().into_iter().rev()
             key=|(name, _)| name.clone()
             children=move |(_name, player): (String, Player)| {
+                // the first other editor that has this player selected
+                let selected_by = move || {
+                    let name = player.name.get();
+                    others
+                        .with(|others| {
+                            others.values().find(|presence| presence.selection.contains(&name)).cloned()
+                        })
+                };
+                // who's dragging or resizing it, and their color
+                let locked_by = move || {
+                    let lease = leases.with(|leases| leases.get(&player.name.get()).cloned())?;
+                    let color = others
+                        .with(|others| {
+                            others.get(&lease.socket_id).map(|presence| presence.color.clone())
+                        })
+                        .unwrap_or_else(|| "orange".to_string());
+                    Some((lease.holder, color))
+                };
                 view! {
                     <div
+                        node_ref=player.element
                         on:mousedown=move |event: MouseEvent| {
+                            if space_pressed() {
+                                // let the canvas handle panning
+                                return;
+                            }
                             event.prevent_default();
-                            if event.button() == 0 {
-                                set_move_click(true);
-                                set_prev_mouse_pos(Position {
-                                    x: event.x(),
-                                    y: event.y(),
-                                });
-                            } else if event.button() == 2 {
-                                set_resize_click(true);
+                            event.stop_propagation();
+                            if event.shift_key() {
+                                player.is_selected.update(|selected| *selected = !*selected);
+                            } else if !player.is_selected.get_untracked() {
+                                players
+                                    .get_untracked()
+                                    .values()
+                                    .for_each(|p| p.is_selected.set(false));
+                                player.is_selected.set(true);
                             }
-                        }
-
-                        on:mousemove={
-                            let move_mouse = move_mouse.clone();
-                            move |event| {
-                                move_mouse(
-                                    player.width,
-                                    player.height,
-                                    player.position,
-                                    player.name,
-                                    event,
-                                )
+                            if !player.is_selected.get_untracked() {
+                                return;
                             }
-                        }
-
-                        on:mouseup=move |_event| {
-                            set_move_click(false);
-                            set_resize_click(false);
-                        }
-
-                        on:mouseleave=move |_| {
-                            set_move_click(false);
-                            set_resize_click(false);
+                            let mode = match event.button() {
+                                0 => DragMode::Move,
+                                2 => DragMode::Resize(Handle::BottomRight),
+                                _ => return,
+                            };
+                            start_drag.run((Position::new(event.x(), event.y()), mode, selected_players()));
                         }
 
                         style="position: absolute; z-index: 2;"
@@ -427,11 +1824,27 @@ fn Players(
                         }
 
                         style:outline=move || {
-                            if resize_click() || move_click() { "3px solid black" } else { "" }
+                            if let Some((_, color)) = locked_by() {
+                                format!("3px dotted {color}")
+                            } else if player.is_selected.get() {
+                                "3px solid black".to_string()
+                            } else if let Some(presence) = selected_by() {
+                                format!("3px dashed {}", presence.color)
+                            } else {
+                                String::new()
+                            }
                         }
 
                         style:cursor=move || {
-                            if resize_click() || move_click() { "move" } else { "" }
+                            if player.is_selected.get()
+                                && drag_start.with(|start| {
+                                    matches!(start, Some(DragStart { mode: DragMode::Move, .. }))
+                                })
+                            {
+                                "move"
+                            } else {
+                                ""
+                            }
                         }
 
                         style:transform=move || {
@@ -439,6 +1852,62 @@ fn Players(
                         }
                     >
 
+                        {move || {
+                            locked_by()
+                                .map(|(holder, color)| (format!("🔒 {holder}"), color))
+                                .or_else(|| selected_by().map(|presence| (presence.name, presence.color)))
+                                .map(|(label, color)| {
+                                    view! {
+                                        // flipped back along with the media, so the name stays readable
+                                        <small
+                                            style="position: absolute; z-index: 3; left: 0; bottom: 100%; padding: 0 0.25rem; color: black; white-space: nowrap; pointer-events: none;"
+                                            style:background=color
+                                            style:transform=move || {
+                                                if player.horizontal_flip.get() { "scaleX(-1)" } else { "" }
+                                            }
+                                        >
+                                            {label}
+                                        </small>
+                                    }
+                                })
+                        }}
+
+                        <Show when=move || player.is_selected.get()>
+                            {Handle::ALL
+                                .into_iter()
+                                .map(|handle| {
+                                    let (direction_x, direction_y) = handle.direction();
+                                    view! {
+                                        <div
+                                            on:mousedown=move |event: MouseEvent| {
+                                                if event.button() != 0 || space_pressed() {
+                                                    return;
+                                                }
+                                                event.prevent_default();
+                                                event.stop_propagation();
+                                                // the handles are mirrored along with the media
+                                                let handle = if player.horizontal_flip.get_untracked() {
+                                                    handle.mirrored()
+                                                } else {
+                                                    handle
+                                                };
+                                                start_drag.run((
+                                                    Position::new(event.x(), event.y()),
+                                                    DragMode::Resize(handle),
+                                                    vec![player],
+                                                ));
+                                            }
+
+                                            style="position: absolute; z-index: 3; width: 10px; height: 10px; background: white; border: 1px solid black; box-sizing: border-box; transform: translate(-50%, -50%);"
+                                            style:left=format!("{}%", (direction_x + 1) * 50)
+                                            style:top=format!("{}%", (direction_y + 1) * 50)
+                                            style:cursor=handle.cursor()
+                                        ></div>
+                                    }
+                                })
+                                .collect_view()}
+                        </Show>
+
                         {move || {
                             match player.media_type {
                                 crate::MediaType::Text => {
@@ -458,14 +1927,6 @@ fn Players(
                                     view! {
                                         <img
                                             style="width: 100%; height: 100%;"
-                                            style:outline=move || {
-                                                if player.is_selected.get() {
-                                                    "3px solid black"
-                                                } else {
-                                                    ""
-                                                }
-                                            }
-
                                             src=player.data.get()
                                         />
                                     }
@@ -475,14 +1936,6 @@ fn Players(
                                     view! {
                                         <video
                                             style="width: 100%; height: 100%;"
-                                            style:outline=move || {
-                                                if player.is_selected.get() {
-                                                    "3px solid black"
-                                                } else {
-                                                    ""
-                                                }
-                                            }
-
                                             autoplay
                                             loop
                                             src=player.data.get()
@@ -503,31 +1956,55 @@ fn Players(
 #[component]
 fn Menu(
     players: ReadSignal<IndexMap<String, Player>>,
+    notice: ReadSignal<Option<String>>,
+    set_notice: WriteSignal<Option<String>>,
     canvas_position: ReadSignal<Position>,
     canvas_zoom: ReadSignal<f64>,
+    screen_size: ReadSignal<ScreenSize>,
+    set_screen_size: WriteSignal<ScreenSize>,
+    snap_settings: ReadSignal<SnapSettings>,
+    set_snap_settings: WriteSignal<SnapSettings>,
+    role: ReadSignal<Option<Role>>,
+    others: ReadSignal<IndexMap<u32, Presence>>,
+    access_token: Signal<Option<String>>,
 ) -> impl IntoView {
     let websocket = expect_context::<WebsocketContext>();
-    let (screen_size, set_screen_size) = signal(ScreenSize::default());
     let (channel, set_channel) = signal(String::from("sadmadladsalman"));
     let (show_stream_player, set_show_stream_player) = signal(true);
     let (interactive_stream_player, set_interactive_stream_player) = signal(false);
 
-    let new_player = {
+    // the library is shared by the library list and the previews in the players list
+    let (assets, set_assets) = signal(Vec::<MediaAsset>::new());
+
+    {
         let websocket = websocket.clone();
-        move |name, data, media_type, x, y, width, height| {
-            websocket.send(
-                &bincode::serialize(&Message::NewMedia {
-                    name,
-                    data,
-                    media_type,
-                    position: Position::new(x, y),
-                    width,
-                    height,
-                })
-                .unwrap(),
-            );
-        }
-    };
+        Effect::new(move |_| {
+            if let ConnectionReadyState::Open = websocket.ready_state.get() {
+                websocket.send(&bincode::serialize(&Message::GetMediaLibrary).unwrap());
+            }
+        });
+    }
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            let Some(message) = websocket.message.get() else {
+                return;
+            };
+            match bincode::deserialize::<Event>(&message) {
+                Ok(Event::MediaLibrary(library)) => set_assets(library),
+                Ok(Event::MediaAdded(asset)) => set_assets.update(|assets| {
+                    if !assets.iter().any(|a| a.id == asset.id) {
+                        assets.push(asset);
+                    }
+                }),
+                Ok(Event::MediaPurged { asset_id }) => {
+                    set_assets.update(|assets| assets.retain(|asset| asset.id != asset_id))
+                }
+                _ => {}
+            }
+        });
+    }
 
     let get_all_players = {
         let websocket = websocket.clone();
@@ -538,36 +2015,12 @@ fn Menu(
 
     let input_element: NodeRef<Input> = NodeRef::new();
     let on_file_submit = {
-        let new_player = new_player.clone();
+        let uploads = expect_context::<Uploads>();
         move || {
             if let Some(files) = input_element.get().unwrap().files() {
                 for i in 0..files.length() {
                     if let Some(file) = files.item(i) {
-                        if file.type_() != "video/webm" && !file.type_().starts_with("image") {
-                            continue;
-                        }
-                        let new_player = new_player.clone();
-                        spawn_local(async move {
-                            if let Ok(file_data) =
-                                wasm_bindgen_futures::JsFuture::from(file.array_buffer()).await
-                            {
-                                let data =
-                                    wasm_bindgen_futures::js_sys::Uint8Array::new(&file_data)
-                                        .to_vec();
-                                let base64 =
-                                    base64::engine::general_purpose::STANDARD.encode(&data);
-
-                                let src = format!("data:{};base64,{base64}", file.type_());
-
-                                let media_type = if file.type_().starts_with("video") {
-                                    MediaType::Video
-                                } else {
-                                    MediaType::Image
-                                };
-
-                                new_player(file.name(), src, media_type, 100, 100, 200, None);
-                            }
-                        });
+                        uploads.upload_file(file, Position::new(100, 100), 200);
                     }
                 }
             }
@@ -580,16 +2033,19 @@ fn Menu(
     let iframe_parent = "localhost";
 
     view! {
-        <h1>{move || format!("State: {}", websocket.ready_state.get())}</h1>
-        <button on:click={
-            let on_file_submit = on_file_submit.clone();
-            move |_event| on_file_submit()
-        }>"Add"</button>
-        <input type="file" accept="video/webm,image/*" node_ref=input_element/>
-        <button on:click={
-            let get_all_players = get_all_players.clone();
-            move |_| get_all_players()
-        }>"Refresh"</button>
+        // keep clicks on these from starting a canvas selection, like the menu below
+        <div on:mousedown=move |event| event.stop_propagation()>
+            <ConnectionHealth/>
+            <button on:click={
+                let on_file_submit = on_file_submit.clone();
+                move |_event| on_file_submit()
+            }>"Add"</button>
+            <input type="file" accept="video/webm,image/*" node_ref=input_element/>
+            <button on:click={
+                let get_all_players = get_all_players.clone();
+                move |_| get_all_players()
+            }>"Refresh"</button>
+        </div>
         <div
             style="z-index: -5000; outline: 3px solid black; position: absolute;"
             style:width=move || format!("{}px", screen_size().width as f64 * canvas_zoom())
@@ -617,7 +2073,33 @@ fn Menu(
             </Show>
         </div>
 
-        <div style="height: 100vh; width: 20vw; background: #535594; position: absolute; left: 0; top: 0; z-index: 5000; margin: 0; padding: 0; box-sizing: border-box; opacity: 90%;">
+        <div
+            // keep clicks in the menu from starting a canvas selection
+            on:mousedown=move |event| event.stop_propagation()
+            style="height: 100vh; width: 20vw; background: #535594; position: absolute; left: 0; top: 0; z-index: 5000; margin: 0; padding: 0; box-sizing: border-box; opacity: 90%;"
+        >
+            <Show when=move || !others.read().is_empty()>
+                <p style="display: flex; flex-wrap: wrap; gap: 0.25rem; margin: 0.5rem;">
+                    "Also here:"
+                    <For
+                        each=move || others().into_values()
+                        key=|presence| (presence.socket_id, presence.name.clone())
+                        children=|presence: Presence| {
+                            view! {
+                                <span
+                                    style="padding: 0 0.25rem; color: black;"
+                                    style:background=presence.color
+                                >
+                                    {presence.name}
+                                </span>
+                            }
+                        }
+                    />
+                </p>
+
+                <hr/>
+            </Show>
+
             <StreamPlayerSettings
                 show_stream_player
                 set_show_stream_player
@@ -633,20 +2115,94 @@ fn Menu(
 
             <hr/>
 
+            <Snapping snap_settings set_snap_settings/>
+
+            <hr/>
+
+            <Show when=move || notice().is_some()>
+                <p style="display: flex; justify-content: space-between; align-items: center; background: #8c3a3a; margin: 0.5rem; padding: 0.25rem;">
+                    <span>{move || notice().unwrap_or_default()}</span>
+                    <button on:click=move |_| set_notice(None) title="Dismiss">
+                        "✕"
+                    </button>
+                </p>
+            </Show>
+
+            <UploadsProgress/>
             <NewText screen_size/>
-            <PlayersList players/>
+            <NewMediaFromUrl/>
+            <Inspector players/>
+            <SelectionActions players/>
+            <Alignment players screen_size/>
+            <PlayersList players assets/>
+
+            <hr/>
+
+            <MediaLibrary assets/>
+
+            <div style:display=move || {
+                if role().is_some_and(|role| role >= Role::Moderator) { "" } else { "none" }
+            }>
+                <hr/>
+
+                <AuditLog access_token/>
+                <Connections/>
+            </div>
+
+            // only the owner manages who can do what
+            <div style:display=move || if role() == Some(Role::Owner) { "" } else { "none" }>
+                <hr/>
+
+                <ApiTokens/>
+                <p>
+                    <a href="/control/settings">"Roles"</a>
+                </p>
+            </div>
         </div>
     }
 }
 
+#[component]
+fn UploadsProgress() -> impl IntoView {
+    let uploads = expect_context::<Uploads>();
+    let uploads_signal = uploads.uploads;
+
+    view! {
+        <For
+            each=move || uploads_signal.get().into_iter().map(|(id, upload)| (id, upload.name, upload.bytes.len()))
+            key=|(id, _, _)| *id
+            children=move |(upload_id, name, total)| {
+                let uploads = uploads.clone();
+                let received = move || {
+                    uploads_signal
+                        .with(|uploads| uploads.get(&upload_id).map(|upload| upload.received))
+                        .unwrap_or_default()
+                };
+                view! {
+                    <p style="display: flex; gap: 0.25rem; align-items: center; margin: 0.5rem;">
+                        <span style="flex: 1; overflow: hidden; text-overflow: ellipsis; white-space: nowrap;">
+                            {name}
+                        </span>
+                        <progress max=total value=received></progress>
+                        <button on:click=move |_| uploads.cancel(upload_id) title="Cancel upload">
+                            "✕"
+                        </button>
+                    </p>
+                }
+            }
+        />
+    }
+}
+
 #[component]
 fn NewText(screen_size: ReadSignal<ScreenSize>) -> impl IntoView {
     let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
     let (show_input, set_show_input) = signal(false);
     let (text_content, set_text_content) = signal(String::new());
 
     let send_new_text = move || {
-        let message = Message::NewMedia {
+        let message = sequencer.sequence(Message::NewMedia {
             name: text_content(),
             data: text_content(),
             media_type: MediaType::Text,
@@ -656,7 +2212,7 @@ fn NewText(screen_size: ReadSignal<ScreenSize>) -> impl IntoView {
             },
             width: 200,
             height: Some(200),
-        };
+        });
         let message = bincode::serialize(&message).unwrap();
         websocket.send(&message);
     };
@@ -688,6 +2244,41 @@ fn NewText(screen_size: ReadSignal<ScreenSize>) -> impl IntoView {
     }
 }
 
+#[component]
+fn NewMediaFromUrl() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
+    let (url, set_url) = signal(String::new());
+
+    let send_url = move || {
+        let message = sequencer.sequence(Message::NewMediaFromUrl {
+            url: url().trim().to_string(),
+            position: Position::new(100, 100),
+            width: 200,
+            height: None,
+        });
+        websocket.send(&bincode::serialize(&message).unwrap());
+        set_url.update(|url| url.clear());
+    };
+
+    view! {
+        <div>
+            <input
+                placeholder="https://..."
+                title="Link to an image or a webm video, the server keeps a copy of it"
+                on:input=move |event| set_url(event_target_value(&event))
+                prop:value=url
+            />
+            <button
+                on:click=move |_| send_url()
+                disabled=move || url().trim().is_empty()
+            >
+                "From URL"
+            </button>
+        </div>
+    }
+}
+
 #[component]
 fn StreamPlayerSettings(
     show_stream_player: ReadSignal<bool>,
@@ -769,56 +2360,533 @@ fn ScreenBorder(
                 }
             />
 
-            <label for="width">"Width"</label>
-            <input
-                id="width"
-                type="number"
-                value=move || screen_size().width
-                on:input=move |event| {
-                    if let Ok(new_width) = event_target_value(&event).parse::<i32>() {
-                        set_screen_size.update(|size| size.width = new_width);
-                    }
+            <label for="width">"Width"</label>
+            <input
+                id="width"
+                type="number"
+                value=move || screen_size().width
+                on:input=move |event| {
+                    if let Ok(new_width) = event_target_value(&event).parse::<i32>() {
+                        set_screen_size.update(|size| size.width = new_width);
+                    }
+                }
+            />
+
+        </div>
+    }
+}
+
+/// Editable properties of the selected player, only shown when exactly one player is selected
+#[component]
+fn Inspector(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
+
+    let selected_player = move || {
+        let mut selected = players()
+            .into_values()
+            .filter(|player| player.is_selected.get());
+        match (selected.next(), selected.next()) {
+            (Some(player), None) => Some(player),
+            _ => None,
+        }
+    };
+
+    let send = move |message: Message| {
+        let message = sequencer.sequence(message);
+        websocket.send(&bincode::serialize(&message).unwrap());
+    };
+
+    view! {
+        {move || {
+            selected_player()
+                .map(|player| {
+                    let set_position = {
+                        let send = send.clone();
+                        move |x: Option<i32>, y: Option<i32>| {
+                            player
+                                .position
+                                .update(|position| {
+                                    position.x = x.unwrap_or(position.x);
+                                    position.y = y.unwrap_or(position.y);
+                                });
+                            send(Message::SetPosition {
+                                player_name: player.name.get_untracked(),
+                                new_position: player.position.get_untracked(),
+                            });
+                        }
+                    };
+                    let set_size = {
+                        let send = send.clone();
+                        move |width: i32, height: Option<i32>| {
+                            player.width.set(width.max(MIN_PLAYER_SIZE));
+                            player.height.set(height.map(|height| height.max(MIN_PLAYER_SIZE)));
+                            send(Message::SetSize {
+                                player_name: player.name.get_untracked(),
+                                width: player.width.get_untracked(),
+                                height: player.height.get_untracked(),
+                            });
+                        }
+                    };
+                    let send = send.clone();
+                    view! {
+                        <div style="display: grid; grid-template-columns: auto 1fr; gap: 0.25rem; text-align: left; padding: 0.5rem;">
+                            <span>"Name"</span>
+                            <span
+                                title=move || player.name.get()
+                                style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis;"
+                            >
+                                {move || player.name.get()}
+                            </span>
+
+                            <span>"Type"</span>
+                            <span>{format!("{:?}", player.media_type)}</span>
+
+                            <label for="inspector-x">"X"</label>
+                            <input
+                                id="inspector-x"
+                                type="number"
+                                prop:value=move || player.position.get().x
+                                on:change={
+                                    let set_position = set_position.clone();
+                                    move |event| {
+                                        if let Ok(x) = event_target_value(&event).parse::<i32>() {
+                                            set_position(Some(x), None);
+                                        }
+                                    }
+                                }
+                            />
+
+                            <label for="inspector-y">"Y"</label>
+                            <input
+                                id="inspector-y"
+                                type="number"
+                                prop:value=move || player.position.get().y
+                                on:change=move |event| {
+                                    if let Ok(y) = event_target_value(&event).parse::<i32>() {
+                                        set_position(None, Some(y));
+                                    }
+                                }
+                            />
+
+                            <label for="inspector-width">"Width"</label>
+                            <input
+                                id="inspector-width"
+                                type="number"
+                                min=MIN_PLAYER_SIZE
+                                prop:value=move || player.width.get()
+                                on:change={
+                                    let set_size = set_size.clone();
+                                    move |event| {
+                                        if let Ok(width) = event_target_value(&event).parse::<i32>() {
+                                            set_size(width, player.height.get_untracked());
+                                        }
+                                    }
+                                }
+                            />
+
+                            <label for="inspector-height">"Height"</label>
+                            <div style="display: flex;">
+                                <input
+                                    id="inspector-height"
+                                    type="number"
+                                    min=MIN_PLAYER_SIZE
+                                    style="min-width: 0; flex-grow: 1;"
+                                    disabled=move || player.height.get().is_none()
+                                    prop:value=move || {
+                                        player.height.get().map(|height| height.to_string()).unwrap_or_default()
+                                    }
+                                    on:change={
+                                        let set_size = set_size.clone();
+                                        move |event| {
+                                            if let Ok(height) = event_target_value(&event).parse::<i32>() {
+                                                set_size(player.width.get_untracked(), Some(height));
+                                            }
+                                        }
+                                    }
+                                />
+                                <label
+                                    for="inspector-auto-height"
+                                    title="Keep the aspect ratio of the media"
+                                >
+                                    "Auto"
+                                </label>
+                                <input
+                                    id="inspector-auto-height"
+                                    type="checkbox"
+                                    prop:checked=move || player.height.get().is_none()
+                                    on:change=move |event| {
+                                        let width = player.width.get_untracked();
+                                        // None means this should keep the aspect ratio of the player and set the height to auto
+                                        let height = if event_target_checked(&event) {
+                                            None
+                                        } else {
+                                            Some(width)
+                                        };
+                                        set_size(width, height);
+                                    }
+                                />
+                            </div>
+
+                            <label for="inspector-flip">"Flipped"</label>
+                            <input
+                                id="inspector-flip"
+                                type="checkbox"
+                                style="justify-self: start;"
+                                prop:checked=move || player.horizontal_flip.get()
+                                on:change=move |event| {
+                                    let is_flipped = event_target_checked(&event);
+                                    player.horizontal_flip.set(is_flipped);
+                                    send(Message::FlipPlayerHorizontally {
+                                        player_name: player.name.get_untracked(),
+                                        is_flipped,
+                                    });
+                                }
+                            />
+                        </div>
+                    }
+                })
+        }}
+    }
+}
+
+#[component]
+fn SelectionActions(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
+
+    let selected_players = move || {
+        players()
+            .into_values()
+            .filter(|player| player.is_selected.get())
+            .collect::<Vec<_>>()
+    };
+
+    let flip_selected = {
+        let websocket = websocket.clone();
+        move || {
+            let messages = selected_players()
+                .into_iter()
+                .map(|player| {
+                    player
+                        .horizontal_flip
+                        .update(|is_flipped| *is_flipped = !*is_flipped);
+                    Message::FlipPlayerHorizontally {
+                        player_name: player.name.get_untracked(),
+                        is_flipped: player.horizontal_flip.get_untracked(),
+                    }
+                })
+                .collect();
+            let message = sequencer.sequence(Message::Batch(messages));
+            websocket.send(&bincode::serialize(&message).unwrap());
+        }
+    };
+
+    let delete_selected = move || {
+        let messages = selected_players()
+            .into_iter()
+            .map(|player| Message::DeletePlayer {
+                player_name: player.name.get_untracked(),
+            })
+            .collect();
+        let message = sequencer.sequence(Message::Batch(messages));
+        websocket.send(&bincode::serialize(&message).unwrap());
+    };
+
+    view! {
+        <Show when=move || !selected_players().is_empty()>
+            <div style="display: flex; align-items: center; justify-content: space-between;">
+                <span>{move || format!("{} selected", selected_players().len())}</span>
+                <div>
+                    <button
+                        on:click={
+                            let flip_selected = flip_selected.clone();
+                            move |_| flip_selected()
+                        }
+
+                        title="Flip selected media horizontally"
+                    >
+                        "↔"
+                    </button>
+                    <button
+                        on:click={
+                            let delete_selected = delete_selected.clone();
+                            move |_| delete_selected()
+                        }
+
+                        title="Remove selected media"
+                    >
+                        "🗑"
+                    </button>
+                </div>
+            </div>
+        </Show>
+    }
+}
+
+#[component]
+fn Snapping(
+    snap_settings: ReadSignal<SnapSettings>,
+    set_snap_settings: WriteSignal<SnapSettings>,
+) -> impl IntoView {
+    view! {
+        <div>
+            <p>"Snapping"</p>
+            <div style="display: flex; justify-content: space-around">
+                <div>
+                    <label for="snap-to-screen">"Screen"</label>
+                    <input
+                        id="snap-to-screen"
+                        type="checkbox"
+                        checked=move || snap_settings().to_screen
+                        on:change=move |event| {
+                            let checked = event_target_checked(&event);
+                            set_snap_settings.update(|settings| settings.to_screen = checked);
+                        }
+                    />
+
+                </div>
+                <div>
+                    <label for="snap-to-players">"Media"</label>
+                    <input
+                        id="snap-to-players"
+                        type="checkbox"
+                        checked=move || snap_settings().to_players
+                        on:change=move |event| {
+                            let checked = event_target_checked(&event);
+                            set_snap_settings.update(|settings| settings.to_players = checked);
+                        }
+                    />
+
+                </div>
+            </div>
+            <label for="grid-size" title="0 disables the grid">"Grid"</label>
+            <input
+                id="grid-size"
+                type="number"
+                min="0"
+                title="0 disables the grid"
+                value=move || snap_settings().grid_size
+                on:input=move |event| {
+                    if let Ok(grid_size) = event_target_value(&event).parse::<i32>() {
+                        set_snap_settings.update(|settings| settings.grid_size = grid_size.max(0));
+                    }
+                }
+            />
+
+        </div>
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    CenterHorizontally,
+    Right,
+    Top,
+    CenterVertically,
+    Bottom,
+}
+
+/// Aligns and distributes the selected players
+///
+/// A single selected player is aligned to the screen border instead of the selection
+#[component]
+fn Alignment(
+    players: ReadSignal<IndexMap<String, Player>>,
+    screen_size: ReadSignal<ScreenSize>,
+) -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
+
+    let selected_players = move || {
+        players()
+            .into_values()
+            .filter(|player| player.is_selected.get())
+            .collect::<Vec<_>>()
+    };
+
+    let send_positions = move |selected: &[Player]| {
+        let messages = selected
+            .iter()
+            .map(|player| Message::SetPosition {
+                player_name: player.name.get_untracked(),
+                new_position: player.position.get_untracked(),
+            })
+            .collect();
+        let message = sequencer.sequence(Message::Batch(messages));
+        websocket.send(&bincode::serialize(&message).unwrap());
+    };
+
+    let align = {
+        let send_positions = send_positions.clone();
+        move |align: Align| {
+            let selected = selected_players();
+            let target = if selected.len() == 1 {
+                let screen = screen_size();
+                Bounds {
+                    left: 0,
+                    top: 0,
+                    right: screen.width,
+                    bottom: screen.height,
+                }
+            } else {
+                let Some(bounds) = selected.iter().map(Bounds::of_player).reduce(Bounds::union)
+                else {
+                    return;
+                };
+                bounds
+            };
+
+            for player in &selected {
+                let bounds = Bounds::of_player(player);
+                player.position.update(|position| match align {
+                    Align::Left => position.x = target.left,
+                    Align::CenterHorizontally => {
+                        position.x = target.center_x() - bounds.width() / 2
+                    }
+                    Align::Right => position.x = target.right - bounds.width(),
+                    Align::Top => position.y = target.top,
+                    Align::CenterVertically => position.y = target.center_y() - bounds.height() / 2,
+                    Align::Bottom => position.y = target.bottom - bounds.height(),
+                });
+            }
+
+            send_positions(&selected);
+        }
+    };
+
+    // spreads the players so the gaps between them are equal, keeping the outermost ones in place
+    let distribute = move |horizontally: bool| {
+        let mut selected = selected_players();
+        if selected.len() < 3 {
+            return;
+        }
+        let start = |player: &Player| {
+            let bounds = Bounds::of_player(player);
+            if horizontally {
+                bounds.left
+            } else {
+                bounds.top
+            }
+        };
+        let size = |player: &Player| {
+            let bounds = Bounds::of_player(player);
+            if horizontally {
+                bounds.width()
+            } else {
+                bounds.height()
+            }
+        };
+        selected.sort_by_key(start);
+
+        let first = &selected[0];
+        let last = &selected[selected.len() - 1];
+        let span = start(last) + size(last) - start(first);
+        let total_size: i32 = selected.iter().map(size).sum();
+        let gap = (span - total_size) / (selected.len() as i32 - 1);
+
+        let mut next = start(first);
+        for player in &selected {
+            player.position.update(|position| {
+                if horizontally {
+                    position.x = next;
+                } else {
+                    position.y = next;
+                }
+            });
+            next += size(player) + gap;
+        }
+
+        send_positions(&selected);
+    };
+
+    let align_button = move |align_to: Align, label: &'static str, title: &'static str| {
+        let align = align.clone();
+        view! {
+            <button on:click=move |_| align(align_to) title=title>
+                {label}
+            </button>
+        }
+    };
+
+    view! {
+        <div style:display=move || if selected_players().is_empty() { "none" } else { "" }>
+            {align_button.clone()(Align::Left, "⇤", "Align left")}
+            {align_button.clone()(Align::CenterHorizontally, "↔", "Center horizontally")}
+            {align_button.clone()(Align::Right, "⇥", "Align right")}
+            {align_button.clone()(Align::Top, "⤒", "Align top")}
+            {align_button.clone()(Align::CenterVertically, "↕", "Center vertically")}
+            {align_button(Align::Bottom, "⤓", "Align bottom")}
+            <button
+                on:click={
+                    let distribute = distribute.clone();
+                    move |_| distribute(true)
                 }
-            />
 
+                disabled=move || selected_players().len() < 3
+                title="Distribute horizontally"
+            >
+                "|||"
+            </button>
+            <button
+                on:click=move |_| distribute(false)
+                disabled=move || selected_players().len() < 3
+                title="Distribute vertically"
+            >
+                "☰"
+            </button>
         </div>
     }
 }
 
 #[component]
-fn PlayersList(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
+fn PlayersList(
+    players: ReadSignal<IndexMap<String, Player>>,
+    assets: ReadSignal<Vec<MediaAsset>>,
+) -> impl IntoView {
     let websocket = expect_context::<WebsocketContext>();
-    let delete = {
+    let sequencer = expect_context::<Sequencer>();
+    let send = {
         let websocket = websocket.clone();
-        move |player_name| {
-            websocket.send(&bincode::serialize(&Message::DeletePlayer { player_name }).unwrap());
+        move |message: Message| {
+            let message = sequencer.sequence(message);
+            websocket.send(&bincode::serialize(&message).unwrap());
         }
     };
 
+    let delete = {
+        let send = send.clone();
+        move |player_name| send(Message::DeletePlayer { player_name })
+    };
+
     let move_up = {
-        let websocket = websocket.clone();
-        move |player_name| {
-            websocket.send(&bincode::serialize(&Message::MovePlayerUp { player_name }).unwrap());
-        }
+        let send = send.clone();
+        move |player_name| send(Message::MovePlayerUp { player_name })
     };
 
     let move_down = {
-        let websocket = websocket.clone();
+        let send = send.clone();
+        move |player_name| send(Message::MovePlayerDown { player_name })
+    };
+
+    let duplicate = {
+        let send = send.clone();
         move |player_name| {
-            websocket.send(&bincode::serialize(&Message::MovePlayerDown { player_name }).unwrap());
+            send(Message::DuplicatePlayer {
+                player_name,
+                offset: DUPLICATE_OFFSET,
+            })
         }
     };
 
     let flip = {
-        let websocket = websocket.clone();
+        let send = send.clone();
         move |player_name, is_flipped| {
-            websocket.send(
-                &bincode::serialize(&Message::FlipPlayerHorizontally {
-                    player_name,
-                    is_flipped,
-                })
-                .unwrap(),
-            );
+            send(Message::FlipPlayerHorizontally {
+                player_name,
+                is_flipped,
+            })
         }
     };
 
@@ -832,12 +2900,15 @@ fn PlayersList(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
                         <li
                             on:click={
                                 let name = name.clone();
-                                move |_event| {
+                                move |event: MouseEvent| {
                                     players()
                                         .iter()
                                         .for_each(|(n, p)| {
                                             if *n != name {
-                                                p.is_selected.set(false)
+                                                // shift adds to the current selection
+                                                if !event.shift_key() {
+                                                    p.is_selected.set(false)
+                                                }
                                             } else {
                                                 p.is_selected
                                                     .update(|selected| {
@@ -854,9 +2925,17 @@ fn PlayersList(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
                             }
                         >
 
+                            {move || {
+                                let data = player.data.get();
+                                let thumbnail = assets
+                                    .with(|assets| {
+                                        assets.iter().find(|asset| asset.src == data)?.thumbnail.clone()
+                                    });
+                                preview(thumbnail, data, player.media_type)
+                            }}
                             <span
                                 title=name.clone()
-                                style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis;"
+                                style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis; flex-grow: 1; text-align: left;"
                             >
                                 {name.clone()}
                             </span>
@@ -902,6 +2981,18 @@ fn PlayersList(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
                                 >
                                     "↔"
                                 </button>
+                                <button
+                                    on:click={
+                                        let duplicate = duplicate.clone();
+                                        let name = name.clone();
+                                        move |_e| duplicate(name.clone())
+                                    }
+
+                                    title="Duplicate media"
+                                    style="height: 100%;"
+                                >
+                                    "⧉"
+                                </button>
                                 <button
                                     on:click={
                                         let delete = delete.clone();
@@ -923,3 +3014,692 @@ fn PlayersList(players: ReadSignal<IndexMap<String, Player>>) -> impl IntoView {
         </ul>
     }
 }
+
+/// A small preview of media, falling back to the media itself when there's no thumbnail
+fn preview(thumbnail: Option<String>, data: String, media_type: MediaType) -> AnyView {
+    const STYLE: &str = "width: 2rem; height: 2rem; object-fit: contain; flex-shrink: 0;";
+
+    match (thumbnail, media_type) {
+        (Some(thumbnail), _) => {
+            view! { <img src=thumbnail loading="lazy" style=STYLE/> }.into_any()
+        }
+        (None, MediaType::Image) => view! { <img src=data loading="lazy" style=STYLE/> }.into_any(),
+        // only loading the metadata is enough for the browser to show the first frame
+        (None, MediaType::Video) => {
+            view! { <video src=data preload="metadata" muted=true style=STYLE></video> }.into_any()
+        }
+        (None, MediaType::Text) => {
+            view! { <span title=data.clone() style=STYLE>"T"</span> }.into_any()
+        }
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+fn format_date(seconds_since_epoch: u64) -> String {
+    wasm_bindgen_futures::js_sys::Date::new(&JsValue::from_f64(seconds_since_epoch as f64 * 1000.0))
+        .to_locale_date_string("default", &JsValue::UNDEFINED)
+        .into()
+}
+
+fn format_date_time(seconds_since_epoch: u64) -> String {
+    wasm_bindgen_futures::js_sys::Date::new(&JsValue::from_f64(seconds_since_epoch as f64 * 1000.0))
+        .to_locale_string("default", &JsValue::UNDEFINED)
+        .into()
+}
+
+/// Everything uploaded or fetched so far, kept by the server even after the players showing it are removed
+#[component]
+fn MediaLibrary(assets: ReadSignal<Vec<MediaAsset>>) -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let sequencer = expect_context::<Sequencer>();
+    let (search, set_search) = signal(String::new());
+    let (show_library, set_show_library) = signal(false);
+    let filtered_assets = move || {
+        let search = search().to_lowercase();
+        assets()
+            .into_iter()
+            .rev()
+            .filter(|asset| asset.name.to_lowercase().contains(&search))
+            .collect::<Vec<_>>()
+    };
+
+    let add = {
+        let websocket = websocket.clone();
+        move |asset_id: String| {
+            let message = sequencer.sequence(Message::NewMediaFromLibrary {
+                asset_id,
+                position: Position::new(100, 100),
+                width: 200,
+                height: None,
+            });
+            websocket.send(&bincode::serialize(&message).unwrap());
+        }
+    };
+
+    let purge = move |asset: &MediaAsset| {
+        let confirmed = window()
+            .confirm_with_message(&format!(
+                "Delete {} from the library? Media showing it will be removed too.",
+                asset.name
+            ))
+            .unwrap_or(false);
+        if confirmed {
+            let message = Message::PurgeMedia {
+                asset_id: asset.id.clone(),
+            };
+            websocket.send(&bincode::serialize(&message).unwrap());
+        }
+    };
+
+    view! {
+        <div>
+            <button on:click=move |_| set_show_library.update(|show| *show = !*show)>
+                {move || format!("Media library ({})", assets().len())}
+            </button>
+            <div style:display=move || if show_library() { "" } else { "none" }>
+                <input
+                    placeholder="Search"
+                    style="width: 90%;"
+                    on:input=move |event| set_search(event_target_value(&event))
+                    prop:value=search
+                />
+                <ul style="width: 100%; margin: 0; padding: 0; box-sizing: border-box; max-height: 40vh; overflow-y: auto;">
+                    <For
+                        each=filtered_assets
+                        key=|asset| asset.id.clone()
+                        children=move |asset: MediaAsset| {
+                            let add = add.clone();
+                            let purge = purge.clone();
+                            let asset_id = asset.id.clone();
+                            let purged_asset = asset.clone();
+                            view! {
+                                <li style="display: flex; align-items: center; gap: 0.25rem; list-style: none; width: 100%; box-sizing: border-box;">
+                                    {preview(asset.thumbnail.clone(), asset.src.clone(), asset.media_type)}
+                                    <div style="display: flex; flex-direction: column; flex-grow: 1; min-width: 0; text-align: left;">
+                                        <span
+                                            title=asset.name.clone()
+                                            style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis;"
+                                        >
+                                            {asset.name.clone()}
+                                        </span>
+                                        <small>
+                                            {format!(
+                                                "{} · {}",
+                                                format_size(asset.size),
+                                                format_date(asset.uploaded_at),
+                                            )}
+                                        </small>
+                                    </div>
+                                    <button
+                                        on:click=move |_| add(asset_id.clone())
+                                        title="Add to the overlay"
+                                    >
+                                        "+"
+                                    </button>
+                                    <button
+                                        on:click=move |_| purge(&purged_asset)
+                                        title="Delete from the library"
+                                    >
+                                        "🗑"
+                                    </button>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </div>
+        </div>
+    }
+}
+
+/// Tokens for bots and overlays that shouldn't need a twitch account
+#[component]
+fn ApiTokens() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let (tokens, set_tokens) = signal(Vec::<ApiToken>::new());
+    let (show_tokens, set_show_tokens) = signal(false);
+    let (name, set_name) = signal(String::new());
+    let (scope, set_scope) = signal(Scope::Overlay);
+    // the secret of the last created token, it can't be shown again once this is dismissed
+    let (new_secret, set_new_secret) = signal(None::<(String, String)>);
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            let Some(message) = websocket.message.get() else {
+                return;
+            };
+            match bincode::deserialize::<Event>(&message) {
+                Ok(Event::ApiTokens(api_tokens)) => set_tokens(api_tokens),
+                Ok(Event::ApiTokenCreated { token, secret }) => {
+                    set_new_secret(Some((token.name, secret)))
+                }
+                _ => {}
+            }
+        });
+    }
+
+    let toggle = {
+        let websocket = websocket.clone();
+        move |_| {
+            set_show_tokens.update(|show| *show = !*show);
+            if show_tokens.get_untracked() {
+                websocket.send(&bincode::serialize(&Message::GetApiTokens).unwrap());
+            }
+        }
+    };
+
+    let create = {
+        let websocket = websocket.clone();
+        move |_| {
+            let token_name = name.get_untracked().trim().to_string();
+            if token_name.is_empty() {
+                return;
+            }
+            let message = Message::CreateApiToken {
+                name: token_name,
+                scope: scope.get_untracked(),
+            };
+            websocket.send(&bincode::serialize(&message).unwrap());
+            set_name(String::new());
+        }
+    };
+
+    let revoke = move |token: &ApiToken| {
+        let confirmed = window()
+            .confirm_with_message(&format!(
+                "Revoke {}? Anything using it loses access right away.",
+                token.name
+            ))
+            .unwrap_or(false);
+        if confirmed {
+            let message = Message::RevokeApiToken {
+                token_id: token.id.clone(),
+            };
+            websocket.send(&bincode::serialize(&message).unwrap());
+        }
+    };
+
+    view! {
+        <div>
+            <button on:click=toggle>"API tokens"</button>
+            <div style:display=move || if show_tokens() { "" } else { "none" }>
+                <Show when=move || new_secret().is_some()>
+                    <p style="background: #3a8c4a; margin: 0.5rem; padding: 0.25rem;">
+                        {move || {
+                            format!(
+                                "Token for {}, copy it now, it won't be shown again:",
+                                new_secret().map(|(name, _)| name).unwrap_or_default(),
+                            )
+                        }}
+                        <input
+                            readonly=true
+                            style="width: 90%;"
+                            prop:value=move || new_secret().map(|(_, secret)| secret).unwrap_or_default()
+                        />
+                        <button on:click=move |_| set_new_secret(None)>"Done"</button>
+                    </p>
+                </Show>
+                <div style="display: flex; gap: 0.25rem; margin: 0.5rem;">
+                    <input
+                        placeholder="Name"
+                        style="flex-grow: 1; min-width: 0;"
+                        on:input=move |event| set_name(event_target_value(&event))
+                        prop:value=name
+                    />
+                    <select on:change=move |event| {
+                        let index = event_target_value(&event).parse::<usize>().unwrap_or(0);
+                        set_scope(Scope::ALL[index.min(Scope::ALL.len() - 1)]);
+                    }>
+                        {Scope::ALL
+                            .iter()
+                            .enumerate()
+                            .map(|(index, option)| {
+                                view! {
+                                    <option value=index selected=move || scope() == *option>
+                                        {format!("{option:?}")}
+                                    </option>
+                                }
+                            })
+                            .collect_view()}
+                    </select>
+                    <button on:click=create>"Create"</button>
+                </div>
+                <ul style="width: 100%; margin: 0; padding: 0; box-sizing: border-box;">
+                    <For
+                        each=tokens
+                        key=|token| token.id.clone()
+                        children=move |token: ApiToken| {
+                            let revoke = revoke.clone();
+                            let revoked_token = token.clone();
+                            view! {
+                                <li style="display: flex; align-items: center; gap: 0.25rem; list-style: none; width: 100%; box-sizing: border-box;">
+                                    <div style="display: flex; flex-direction: column; flex-grow: 1; min-width: 0; text-align: left;">
+                                        <span
+                                            title=token.name.clone()
+                                            style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis;"
+                                        >
+                                            {token.name.clone()}
+                                        </span>
+                                        <small>
+                                            {format!(
+                                                "{:?} · {}",
+                                                token.scope,
+                                                format_date(token.created_at),
+                                            )}
+                                        </small>
+                                    </div>
+                                    <button
+                                        on:click=move |_| revoke(&revoked_token)
+                                        title="Revoke token"
+                                    >
+                                        "🗑"
+                                    </button>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </div>
+        </div>
+    }
+}
+
+/// Who changed what, newest first, filtered by the server
+#[component]
+fn AuditLog(access_token: Signal<Option<String>>) -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let (entries, set_entries) = signal(Vec::<AuditEntry>::new());
+    let (show_log, set_show_log) = signal(false);
+    let (actor, set_actor) = signal(String::new());
+    let (action, set_action) = signal(String::new());
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            if let Some(message) = websocket.message.get()
+                && let Ok(Event::AuditLog(audit_entries)) = bincode::deserialize::<Event>(&message)
+            {
+                set_entries(audit_entries);
+            }
+        });
+    }
+
+    let filter = |value: String| Some(value.trim().to_string()).filter(|value| !value.is_empty());
+    let refresh = Callback::new(move |()| {
+        let message = Message::GetAuditLog {
+            actor: filter(actor.get_untracked()),
+            action: filter(action.get_untracked()),
+        };
+        websocket.send(&bincode::serialize(&message).unwrap());
+    });
+
+    let toggle = move |_| {
+        set_show_log.update(|show| *show = !*show);
+        if show_log.get_untracked() {
+            refresh.run(());
+        }
+    };
+
+    // the server exports every matching entry, not only the page shown here
+    let download: NodeRef<A> = NodeRef::new();
+    let export = move |_| {
+        let Some(access_token) = access_token.get_untracked() else {
+            return;
+        };
+        let actor = filter(actor.get_untracked());
+        let action = filter(action.get_untracked());
+        spawn_local(async move {
+            use base64::Engine;
+
+            match export_audit_log(access_token, actor, action).await {
+                Ok(lines) => {
+                    if let Some(download) = download.get_untracked() {
+                        download.set_href(&format!(
+                            "data:application/jsonl;base64,{}",
+                            base64::engine::general_purpose::STANDARD.encode(lines)
+                        ));
+                        download.click();
+                    }
+                }
+                Err(e) => tracing::warn!("couldn't export the audit log: {e}"),
+            }
+        });
+    };
+
+    view! {
+        <div>
+            <button on:click=toggle>"Audit log"</button>
+            <div style:display=move || if show_log() { "" } else { "none" }>
+                <div style="display: flex; gap: 0.25rem; margin: 0.5rem;">
+                    <input
+                        placeholder="Who"
+                        style="flex-grow: 1; min-width: 0;"
+                        on:input=move |event| set_actor(event_target_value(&event))
+                        on:change=move |_| refresh.run(())
+                        prop:value=actor
+                    />
+                    <input
+                        placeholder="Action"
+                        style="flex-grow: 1; min-width: 0;"
+                        on:input=move |event| set_action(event_target_value(&event))
+                        on:change=move |_| refresh.run(())
+                        prop:value=action
+                    />
+                    <button on:click=move |_| refresh.run(()) title="Refresh">
+                        "↻"
+                    </button>
+                    <button on:click=export>"Export"</button>
+                    <a node_ref=download download="audit.jsonl" style="display: none;"></a>
+                </div>
+                <Show when=move || entries.read().is_empty()>
+                    <p>"Nothing recorded"</p>
+                </Show>
+                <ul style="width: 100%; max-height: 20rem; overflow-y: auto; margin: 0; padding: 0; box-sizing: border-box;">
+                    <For
+                        each=entries
+                        key=|entry| (entry.at, entry.socket_id, entry.action.clone(), entry.details.clone())
+                        children=move |entry: AuditEntry| {
+                            view! {
+                                <li style="display: flex; flex-direction: column; list-style: none; text-align: left; margin-bottom: 0.25rem;">
+                                    <span
+                                        title=entry.details.clone()
+                                        style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis;"
+                                    >
+                                        {format!("{} {}", entry.action, entry.details)}
+                                    </span>
+                                    <small>
+                                        {format!(
+                                            "{} · {} · {}",
+                                            entry.actor,
+                                            entry
+                                                .socket_id
+                                                .map(|socket_id| format!("socket {socket_id}"))
+                                                .unwrap_or_else(|| "api".to_string()),
+                                            format_date_time(entry.at),
+                                        )}
+                                    </small>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </div>
+        </div>
+    }
+}
+
+/// Whether the connection works and how long the last ping took
+#[component]
+fn ConnectionHealth() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let heartbeat = expect_context::<Heartbeat>();
+
+    let health = move || match websocket.ready_state.get() {
+        ConnectionReadyState::Open if !heartbeat.is_responding.get() => {
+            ("orange", "Not responding".to_string())
+        }
+        ConnectionReadyState::Open => (
+            "limegreen",
+            match heartbeat.latency.get() {
+                Some(latency) => format!("Connected · {latency:.0} ms"),
+                None => "Connected".to_string(),
+            },
+        ),
+        ConnectionReadyState::Connecting => ("orange", "Connecting".to_string()),
+        ConnectionReadyState::Closing | ConnectionReadyState::Closed => {
+            ("red", "Disconnected".to_string())
+        }
+    };
+
+    view! {
+        <h1 style="display: flex; align-items: center; justify-content: center; gap: 0.5rem;">
+            <span
+                style="display: inline-block; width: 0.75rem; height: 0.75rem; border-radius: 50%;"
+                style:background-color=move || health().0
+            ></span>
+            {move || health().1}
+        </h1>
+    }
+}
+
+/// Every connected overlay and control page, moderators can disconnect them
+#[component]
+fn Connections() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let (connections, set_connections) = signal(Vec::<ConnectionInfo>::new());
+    let (show_connections, set_show_connections) = signal(false);
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            if let Some(message) = websocket.message.get()
+                && let Ok(Event::Connections(all_connections)) =
+                    bincode::deserialize::<Event>(&message)
+            {
+                set_connections(all_connections);
+            }
+        });
+    }
+
+    let refresh = {
+        let websocket = websocket.clone();
+        Callback::new(move |()| {
+            websocket.send(&bincode::serialize(&Message::GetConnections).unwrap());
+        })
+    };
+
+    let kick = Callback::new(move |socket_id: u32| {
+        websocket.send(&bincode::serialize(&Message::KickConnection { socket_id }).unwrap());
+    });
+
+    let toggle = move |_| {
+        set_show_connections.update(|show| *show = !*show);
+        if show_connections.get_untracked() {
+            refresh.run(());
+        }
+    };
+
+    view! {
+        <div>
+            <button on:click=toggle>"Connections"</button>
+            <div style:display=move || if show_connections() { "" } else { "none" }>
+                <div style="display: flex; justify-content: flex-end; margin: 0.5rem;">
+                    <button on:click=move |_| refresh.run(()) title="Refresh">
+                        "↻"
+                    </button>
+                </div>
+                <ul style="width: 100%; max-height: 20rem; overflow-y: auto; margin: 0; padding: 0; box-sizing: border-box;">
+                    <For
+                        each=connections
+                        key=|connection| (connection.socket_id, connection.kind, connection.identity.clone())
+                        children=move |connection: ConnectionInfo| {
+                            let socket_id = connection.socket_id;
+                            view! {
+                                <li style="display: flex; align-items: center; gap: 0.25rem; list-style: none; text-align: left; margin-bottom: 0.25rem;">
+                                    <div style="display: flex; flex-direction: column; flex-grow: 1; min-width: 0;">
+                                        <span style="overflow: hidden; white-space: nowrap; text-overflow: ellipsis;">
+                                            {format!(
+                                                "{} {}",
+                                                match connection.kind {
+                                                    ConnectionKind::Overlay => "Overlay",
+                                                    ConnectionKind::Control => "Control",
+                                                },
+                                                connection.identity.as_deref().unwrap_or(""),
+                                            )}
+                                        </span>
+                                        <small>
+                                            {format!(
+                                                "socket {} · {} · since {}",
+                                                connection.socket_id,
+                                                connection.remote_address,
+                                                format_date_time(connection.connected_at),
+                                            )}
+                                        </small>
+                                    </div>
+                                    <button on:click=move |_| kick.run(socket_id) title="Disconnect">
+                                        "Kick"
+                                    </button>
+                                </li>
+                            }
+                        }
+                    />
+                </ul>
+            </div>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_the_closest_edge() {
+        // the right edge is 3 away from 103, the left one 5 away from -5
+        assert_eq!(snap_offset([0, 50, 100], &[-5, 103], 8), Some((3, 103)));
+        assert_eq!(snap_offset([0, 50, 100], &[-5, 300], 8), Some((-5, -5)));
+        assert_eq!(snap_offset([0, 50, 100], &[46], 8), Some((-4, 46)));
+    }
+
+    #[test]
+    fn snaps_up_to_the_threshold() {
+        assert_eq!(snap_offset([0, 50, 100], &[108], 8), Some((8, 108)));
+        assert_eq!(snap_offset([0, 50, 100], &[-8], 8), Some((-8, -8)));
+        assert_eq!(snap_offset([0, 50, 100], &[109, -9], 8), None);
+    }
+
+    #[test]
+    fn nothing_to_snap_to() {
+        assert_eq!(snap_offset([0, 50, 100], &[], 8), None);
+    }
+
+    /// 100 wide and 50 high
+    const START: Bounds = Bounds {
+        left: 100,
+        top: 100,
+        right: 200,
+        bottom: 150,
+    };
+
+    fn bounds(left: i32, top: i32, right: i32, bottom: i32) -> Bounds {
+        Bounds {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn each_handle_keeps_the_opposite_edges() {
+        let resized = [
+            (Handle::TopLeft, bounds(110, 110, 200, 150)),
+            (Handle::Top, bounds(100, 110, 200, 150)),
+            (Handle::TopRight, bounds(100, 110, 210, 150)),
+            (Handle::Right, bounds(100, 100, 210, 150)),
+            (Handle::BottomRight, bounds(100, 100, 210, 160)),
+            (Handle::Bottom, bounds(100, 100, 200, 160)),
+            (Handle::BottomLeft, bounds(110, 100, 200, 160)),
+            (Handle::Left, bounds(110, 100, 200, 150)),
+        ];
+
+        for (index, (handle, expected)) in resized.into_iter().enumerate() {
+            assert_eq!(
+                resize_bounds(START, handle, Position::new(10, 10), false, false),
+                expected,
+                "handle {index}"
+            );
+        }
+    }
+
+    #[test]
+    fn not_moving_changes_nothing() {
+        for handle in Handle::ALL {
+            assert_eq!(
+                resize_bounds(START, handle, Position::new(0, 0), false, false),
+                START
+            );
+            assert_eq!(
+                resize_bounds(START, handle, Position::new(0, 0), true, true),
+                START
+            );
+        }
+    }
+
+    #[test]
+    fn clamps_to_the_minimum_size() {
+        let shrunk = resize_bounds(
+            START,
+            Handle::BottomRight,
+            Position::new(-500, -500),
+            false,
+            false,
+        );
+        assert_eq!(
+            shrunk,
+            bounds(100, 100, 100 + MIN_PLAYER_SIZE, 100 + MIN_PLAYER_SIZE)
+        );
+
+        // dragged past the opposite edge, it stays at that edge instead of flipping
+        let shrunk = resize_bounds(
+            START,
+            Handle::TopLeft,
+            Position::new(500, 500),
+            false,
+            false,
+        );
+        assert_eq!(
+            shrunk,
+            bounds(200 - MIN_PLAYER_SIZE, 150 - MIN_PLAYER_SIZE, 200, 150)
+        );
+
+        // the height following the width can't go below it either
+        let shrunk = resize_bounds(START, Handle::Right, Position::new(-95, 0), true, false);
+        assert_eq!(shrunk.width(), MIN_PLAYER_SIZE);
+        assert_eq!(shrunk.height(), MIN_PLAYER_SIZE);
+    }
+
+    #[test]
+    fn resizes_from_the_center() {
+        assert_eq!(
+            resize_bounds(START, Handle::Right, Position::new(10, 0), false, true),
+            bounds(90, 100, 210, 150)
+        );
+        assert_eq!(
+            resize_bounds(START, Handle::TopLeft, Position::new(-10, -10), false, true),
+            bounds(90, 90, 210, 160)
+        );
+    }
+
+    #[test]
+    fn keeps_the_aspect_ratio() {
+        // the width changed the most, so the height follows it
+        assert_eq!(
+            resize_bounds(
+                START,
+                Handle::BottomRight,
+                Position::new(50, 0),
+                true,
+                false
+            ),
+            bounds(100, 100, 250, 175)
+        );
+        // side handles grow the other side around the middle
+        assert_eq!(
+            resize_bounds(START, Handle::Right, Position::new(100, 0), true, false),
+            bounds(100, 75, 300, 175)
+        );
+        assert_eq!(
+            resize_bounds(START, Handle::Bottom, Position::new(0, 50), true, false),
+            bounds(50, 100, 250, 200)
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Hashes and hex encoding shared by the sessions, api tokens and media library
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// The sha256 of `bytes` as lowercase hex
+pub fn hash(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn unhex(hex: &str) -> Option<Vec<u8>> {
+    // from_str_radix would take a sign as well
+    if hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `bytes` random bytes as hex
+pub fn random_hex(bytes: usize) -> String {
+    let mut random = vec![0; bytes];
+    rand::rng().fill_bytes(&mut random);
+
+    hex(&random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unhex_reverses_hex() {
+        let bytes = [0x00, 0x01, 0xab, 0xff];
+
+        assert_eq!(unhex(&hex(&bytes)), Some(bytes.to_vec()));
+        assert_eq!(unhex("ABFF"), Some(vec![0xab, 0xff]));
+        assert_eq!(unhex(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn unhex_rejects_invalid_hex() {
+        assert_eq!(unhex("abc"), None);
+        assert_eq!(unhex("zz"), None);
+        assert_eq!(unhex("+1"), None);
+        // two bytes, but not two hex digits
+        assert_eq!(unhex("é"), None);
+        assert_eq!(unhex("éé"), None);
+    }
+
+    #[test]
+    fn hash_is_lowercase_sha256_hex() {
+        assert_eq!(
+            hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
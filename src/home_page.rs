@@ -4,7 +4,7 @@ use leptos_use::{core::ConnectionReadyState, use_interval_fn};
 
 use crate::{
     Message, Player,
-    app::{WebsocketContext, handle_websocket_message},
+    app::{WebsocketContext, handle_websocket_message, request_players},
 };
 
 #[component]
@@ -17,6 +17,8 @@ fn Players() -> impl IntoView {
     // let owner = leptos::Owner::current().expect("there should be an owner");
     let (players, set_players) = signal(IndexMap::<String, Player>::new());
     let websocket = expect_context::<WebsocketContext>();
+    // the revision of the players this page has, so reconnecting only needs what changed since
+    let revision = StoredValue::new(None::<u64>);
 
     {
         let websocket = websocket.clone();
@@ -31,7 +33,7 @@ fn Players() -> impl IntoView {
         let websocket = websocket.clone();
         Effect::new(move |_| {
             if let ConnectionReadyState::Open = websocket.ready_state.get() {
-                websocket.send(&bincode::serialize(&Message::GetAllPlayers).unwrap());
+                request_players(&websocket, revision);
             }
         });
     }
@@ -40,7 +42,7 @@ fn Players() -> impl IntoView {
         let websocket = websocket.clone();
         Effect::new(move |_| {
             if let ConnectionReadyState::Open = websocket.ready_state.get() {
-                handle_websocket_message(websocket.clone(), set_players.clone());
+                handle_websocket_message(websocket.clone(), set_players.clone(), revision);
             }
         });
     }
@@ -48,7 +50,9 @@ fn Players() -> impl IntoView {
     {
         let websocket = websocket.clone();
         Effect::new(move |_| {
-            if let ConnectionReadyState::Closed = websocket.ready_state.get() {
+            if let ConnectionReadyState::Closed = websocket.ready_state.get()
+                && websocket.kicked.with_untracked(Option::is_none)
+            {
                 websocket.open();
             }
         });
@@ -0,0 +1,27 @@
+//! The json files the stores keep their state in
+
+use std::{io, path::Path};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Reads `path`, starting from the default if there is none or it can't be read or parsed
+///
+/// `what` names the contents in the logged errors
+pub async fn load<T: DeserializeOwned + Default>(path: impl AsRef<Path>, what: &str) -> T {
+    match tokio::fs::read(path).await {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+            tracing::error!("couldn't parse the {what}: {e}");
+            T::default()
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => T::default(),
+        Err(e) => {
+            tracing::error!("couldn't read the {what}: {e}");
+            T::default()
+        }
+    }
+}
+
+pub async fn save(path: impl AsRef<Path>, value: &impl Serialize) -> io::Result<()> {
+    let contents = serde_json::to_vec_pretty(value).expect("stored state should serialize");
+    tokio::fs::write(path, contents).await
+}
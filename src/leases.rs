@@ -0,0 +1,188 @@
+//! Short-lived edit leases, so two editors dragging the same player don't fight over it
+
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use tokio::time::Instant;
+
+use crate::Lease;
+
+/// How long a lease lasts without edits from its holder, in case the release never arrives
+pub const LEASE_DURATION: Duration = Duration::from_secs(10);
+/// How often leases that ran out are removed
+pub const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct HeldLease {
+    lease: Lease,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct LeaseStore {
+    leases: IndexMap<String, HeldLease>,
+}
+
+impl LeaseStore {
+    /// Leases the players nobody else holds, returning the ones the socket didn't hold already
+    pub fn acquire(
+        &mut self,
+        socket_id: u32,
+        holder: &str,
+        player_names: Vec<String>,
+    ) -> Vec<String> {
+        let mut granted = Vec::new();
+        for player_name in player_names {
+            if self.check(socket_id, &player_name).is_err() {
+                continue;
+            }
+            if self
+                .leases
+                .get(&player_name)
+                .is_none_or(|held| held.lease.socket_id != socket_id)
+            {
+                granted.push(player_name.clone());
+            }
+            self.leases.insert(
+                player_name,
+                HeldLease {
+                    lease: Lease {
+                        socket_id,
+                        holder: holder.to_string(),
+                    },
+                    expires_at: Instant::now() + LEASE_DURATION,
+                },
+            );
+        }
+
+        granted
+    }
+
+    /// Whether the socket may edit the player, edits by the holder keep the lease alive
+    pub fn check(&mut self, socket_id: u32, player_name: &str) -> Result<(), Lease> {
+        let Some(held) = self.leases.get_mut(player_name) else {
+            return Ok(());
+        };
+        if held.lease.socket_id == socket_id {
+            held.expires_at = Instant::now() + LEASE_DURATION;
+            return Ok(());
+        }
+        if held.expires_at <= Instant::now() {
+            return Ok(());
+        }
+
+        Err(held.lease.clone())
+    }
+
+    /// Returns whether the socket held anything
+    pub fn release(&mut self, socket_id: u32) -> bool {
+        let count = self.leases.len();
+        self.leases
+            .retain(|_, held| held.lease.socket_id != socket_id);
+
+        self.leases.len() != count
+    }
+
+    /// Removes the leases that ran out, returns whether there were any
+    pub fn expire(&mut self) -> bool {
+        let now = Instant::now();
+        let count = self.leases.len();
+        self.leases.retain(|_, held| held.expires_at > now);
+
+        self.leases.len() != count
+    }
+
+    pub fn leases(&self) -> IndexMap<String, Lease> {
+        self.leases
+            .iter()
+            .map(|(player_name, held)| (player_name.clone(), held.lease.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(player_names: &[&str]) -> Vec<String> {
+        player_names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Lets the lease on `player_name` run out without waiting for it
+    fn run_out(store: &mut LeaseStore, player_name: &str) {
+        store.leases[player_name].expires_at = Instant::now();
+    }
+
+    #[test]
+    fn acquires_players_nobody_holds() {
+        let mut store = LeaseStore::default();
+
+        assert_eq!(store.acquire(1, "someone", names(&["a", "b"])), ["a", "b"]);
+        assert_eq!(store.leases()["a"].holder, "someone");
+        assert!(store.check(1, "a").is_ok());
+        assert_eq!(store.check(2, "a").unwrap_err().socket_id, 1);
+        assert!(store.check(2, "c").is_ok());
+    }
+
+    #[test]
+    fn only_grants_what_the_socket_didnt_hold() {
+        let mut store = LeaseStore::default();
+        store.acquire(1, "someone", names(&["a"]));
+
+        assert_eq!(store.acquire(1, "someone", names(&["a", "b"])), ["b"]);
+        assert!(
+            store
+                .acquire(2, "someone else", names(&["a", "b"]))
+                .is_empty()
+        );
+        assert_eq!(store.leases()["b"].socket_id, 1);
+    }
+
+    #[test]
+    fn expired_leases_can_be_taken_over() {
+        let mut store = LeaseStore::default();
+        store.acquire(1, "someone", names(&["a"]));
+        run_out(&mut store, "a");
+
+        assert!(store.check(2, "a").is_ok());
+        assert_eq!(store.acquire(2, "someone else", names(&["a"])), ["a"]);
+        assert_eq!(store.leases()["a"].socket_id, 2);
+    }
+
+    #[test]
+    fn expires_only_leases_that_ran_out() {
+        let mut store = LeaseStore::default();
+        assert!(!store.expire());
+
+        store.acquire(1, "someone", names(&["a", "b"]));
+        assert!(!store.expire());
+
+        run_out(&mut store, "a");
+        assert!(store.expire());
+        assert_eq!(store.leases().keys().collect::<Vec<_>>(), ["b"]);
+        assert!(!store.expire());
+    }
+
+    #[test]
+    fn edits_by_the_holder_keep_the_lease() {
+        let mut store = LeaseStore::default();
+        store.acquire(1, "someone", names(&["a"]));
+        run_out(&mut store, "a");
+
+        assert!(store.check(1, "a").is_ok());
+        assert!(!store.expire());
+        assert!(store.check(2, "a").is_err());
+    }
+
+    #[test]
+    fn releases_every_lease_of_the_socket() {
+        let mut store = LeaseStore::default();
+        store.acquire(1, "someone", names(&["a", "b"]));
+        store.acquire(2, "someone else", names(&["c"]));
+
+        assert!(store.release(1));
+        assert!(!store.release(1));
+        assert_eq!(store.leases().keys().collect::<Vec<_>>(), ["c"]);
+        assert!(store.check(2, "a").is_ok());
+    }
+}
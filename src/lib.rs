@@ -9,6 +9,7 @@ pub mod app;
 pub mod control_page;
 pub mod home_page;
 pub mod server;
+pub mod settings_page;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "ssr")] {
@@ -16,7 +17,19 @@ cfg_if::cfg_if! {
         use std::sync::Arc;
         use axum::extract::FromRef;
 
+        pub mod api;
+        pub mod audit;
+        pub mod auth;
+        pub mod connections;
         pub mod fileserv;
+        pub mod hashing;
+        pub mod json_file;
+        pub mod leases;
+        pub mod media;
+        pub mod revisions;
+        pub mod roles;
+        pub mod scenes;
+        pub mod tokens;
     }
 }
 
@@ -47,14 +60,71 @@ pub fn shell(options: LeptosOptions) -> impl IntoView {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The smallest width or height a player can be resized to
+pub const MIN_PLAYER_SIZE: i32 = 10;
+/// Files are uploaded in pieces of this size, instead of one giant websocket message
+pub const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+/// Uploads a socket can have going at once, the control page queues the others
+pub const MAX_UPLOADS_PER_SOCKET: usize = 4;
+/// Bump whenever [`Message`] or [`Event`] change in a way older builds can't decode,
+/// like reordering, removing or changing variants
+///
+/// Clients send it when connecting, ones with a different version are told to reload
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Close code telling a client its protocol is outdated and it should reload the page
+pub const RELOAD_CLOSE_CODE: u16 = 4000;
+/// Close code telling a client a moderator disconnected it, so it shouldn't reconnect on its own
+pub const KICKED_CLOSE_CODE: u16 = 4001;
+/// Websocket subprotocol for tools that can't speak bincode, [`Message`]s and [`Event`]s are sent as json text frames
+///
+/// The schema of both is served at `/ws/schema.json`
+pub const JSON_SUBPROTOCOL: &str = "strim-overlay.json";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
 pub enum MediaType {
     Text,
     Image,
     Video,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Content types the media library takes, with the extensions links to them end in,
+/// the first one is the extension they're stored with
+///
+/// Svgs aren't supported, served from the same origin their scripts could read the session
+const SUPPORTED_MEDIA: [(&str, MediaType, &[&str]); 6] = [
+    ("image/png", MediaType::Image, &["png"]),
+    ("image/jpeg", MediaType::Image, &["jpg", "jpeg"]),
+    ("image/gif", MediaType::Image, &["gif"]),
+    ("image/webp", MediaType::Image, &["webp"]),
+    ("image/avif", MediaType::Image, &["avif"]),
+    ("video/webm", MediaType::Video, &["webm"]),
+];
+
+impl MediaType {
+    /// The media type and the extension to store it with, `None` if the library doesn't take it
+    pub fn of_content_type(content_type: &str) -> Option<(MediaType, &'static str)> {
+        SUPPORTED_MEDIA
+            .iter()
+            .find(|(supported, _, _)| *supported == content_type)
+            .map(|(_, media_type, extensions)| (*media_type, extensions[0]))
+    }
+
+    /// Guesses the media type of an http(s) link from its file extension
+    pub fn of_url(url: &str) -> Option<MediaType> {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return None;
+        }
+        let path = url.split(['?', '#']).next()?.to_lowercase();
+        let extension = path.rsplit_once('.')?.1;
+        SUPPORTED_MEDIA
+            .iter()
+            .find(|(_, _, extensions)| extensions.contains(&extension))
+            .map(|(_, media_type, _)| *media_type)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Player {
     pub name: RwSignal<String>,
     pub data: RwSignal<String>,
@@ -65,6 +135,9 @@ pub struct Player {
     pub height: RwSignal<Option<i32>>,
     pub is_selected: RwSignal<bool>,
     pub horizontal_flip: RwSignal<bool>,
+    /// Where the control page renders it, auto heights are only known from there
+    #[serde(skip)]
+    pub element: NodeRef<leptos::html::Div>,
 }
 
 impl From<ServerPlayer> for Player {
@@ -78,11 +151,13 @@ impl From<ServerPlayer> for Player {
             height: RwSignal::new(value.height),
             is_selected: RwSignal::new(false),
             horizontal_flip: RwSignal::new(value.horizontal_flip),
+            element: NodeRef::new(),
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
 pub struct ServerPlayer {
     pub name: String,
     pub data: String,
@@ -94,7 +169,140 @@ pub struct ServerPlayer {
     horizontal_flip: bool,
 }
 
+/// Uploaded or fetched media kept by the server, independently of the players showing it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub struct MediaAsset {
+    pub id: String,
+    pub name: String,
+    pub media_type: MediaType,
+    /// In bytes
+    pub size: u64,
+    /// Where the media is served from, used as the data of players showing it
+    pub src: String,
+    pub thumbnail: Option<String>,
+    /// Seconds since the unix epoch
+    pub uploaded_at: u64,
+}
+
+/// What someone using the control page is allowed to do, each role includes the ones before it
+///
+/// The broadcaster is always the owner, twitch moderators are moderators unless the owner assigned them something else
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub enum Role {
+    /// Only reading
+    Viewer,
+    /// Moving, resizing, flipping and reordering media
+    Moderator,
+    /// Adding, duplicating and removing media and managing the media library
+    Editor,
+    /// Everything, including managing roles and api tokens
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::Moderator, Role::Editor, Role::Owner];
+}
+
+/// What an api token is allowed to do, each scope includes the ones before it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub enum Scope {
+    /// Only reading, for overlays
+    Overlay,
+    /// Moving, resizing, flipping and reordering media
+    Arrange,
+    /// Everything, including adding and removing media and managing api tokens
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Overlay, Scope::Arrange, Scope::Admin];
+
+    /// The role the token acts as
+    pub fn role(self) -> Role {
+        match self {
+            Scope::Overlay => Role::Viewer,
+            Scope::Arrange => Role::Moderator,
+            Scope::Admin => Role::Owner,
+        }
+    }
+}
+
+/// A locally issued token for bots and overlays, the secret is only shown once when it's created
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub scope: Scope,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// A change someone made, as kept in the append-only audit log
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch
+    pub at: u64,
+    /// `None` for changes made through the rest api
+    pub socket_id: Option<u32>,
+    /// The twitch login or api token name of whoever made the change
+    pub actor: String,
+    /// Name of the [`Message`], like `DeletePlayer`
+    pub action: String,
+    pub details: String,
+}
+
+/// Someone on the control page, as the other editors see them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub struct Presence {
+    pub socket_id: u32,
+    /// The twitch login or api token name
+    pub name: String,
+    /// Css color their selection and cursor are shown in, the same name always gets the same color
+    pub color: String,
+    /// Names of the players they have selected
+    pub selection: Vec<String>,
+    /// In overlay coordinates, `None` while the mouse isn't over the page
+    pub cursor: Option<Position>,
+}
+
+/// A player someone is dragging or resizing, nobody else can change it until they let go
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub struct Lease {
+    pub socket_id: u32,
+    /// The twitch login or api token name
+    pub holder: String,
+}
+
+/// What a socket is used for, sockets count as overlays until they authorize
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub enum ConnectionKind {
+    Overlay,
+    Control,
+}
+
+/// A connected socket, as listed on the control page
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
+pub struct ConnectionInfo {
+    pub socket_id: u32,
+    pub kind: ConnectionKind,
+    /// The twitch login or api token name, `None` until the socket authorizes
+    pub identity: Option<String>,
+    pub remote_address: String,
+    /// Seconds since the unix epoch
+    pub connected_at: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
 pub struct Position {
     x: i32,
     y: i32,
@@ -145,12 +353,36 @@ pub struct AppState {
     #[cfg(feature = "ssr")]
     pub players: Arc<RwLock<IndexMap<String, ServerPlayer>>>,
     #[cfg(feature = "ssr")]
-    pub broadcaster: tokio::sync::broadcast::Sender<(u32, Event)>,
+    pub broadcaster: revisions::Broadcaster,
+    #[cfg(feature = "ssr")]
+    pub media_library: Arc<RwLock<media::MediaLibrary>>,
+    #[cfg(feature = "ssr")]
+    pub tokens: Arc<RwLock<tokens::TokenStore>>,
+    #[cfg(feature = "ssr")]
+    pub roles: Arc<RwLock<roles::RoleStore>>,
+    #[cfg(feature = "ssr")]
+    pub audit_log: Arc<RwLock<audit::AuditLog>>,
+    #[cfg(feature = "ssr")]
+    pub scenes: Arc<RwLock<scenes::SceneStore>>,
+    /// Authorized sockets by id, shown to each other on the control page
+    #[cfg(feature = "ssr")]
+    pub presence: Arc<RwLock<IndexMap<u32, Presence>>>,
+    #[cfg(feature = "ssr")]
+    pub leases: Arc<RwLock<leases::LeaseStore>>,
+    /// Every connected socket by id, also hands out the ids
+    #[cfg(feature = "ssr")]
+    pub connections: Arc<RwLock<connections::ConnectionRegistry>>,
+    /// In bytes, applies to uploaded and fetched media
+    #[cfg(feature = "ssr")]
+    pub max_upload_size: usize,
     pub leptos_options: LeptosOptions,
 }
 
 /// Messages from frontend to backend
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// Variants are encoded by index, so new ones go at the end, anything else needs a [`PROTOCOL_VERSION`] bump
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
 pub enum Message {
     Ping,
     Authorize(String),
@@ -185,10 +417,174 @@ pub enum Message {
         player_name: String,
         is_flipped: bool,
     },
+    /// Fetches the media at `url` on the server and adds a player showing the fetched copy
+    NewMediaFromUrl {
+        url: String,
+        position: Position,
+        width: i32,
+        height: Option<i32>,
+    },
+    /// Starts a chunked upload, a player showing the file is added once all chunks arrived
+    StartUpload {
+        /// Picked by the client, only has to be unique per connection
+        upload_id: u32,
+        name: String,
+        content_type: String,
+        /// In bytes
+        size: u64,
+        position: Position,
+        width: i32,
+        height: Option<i32>,
+    },
+    /// The next part of an upload, sent after the previous one was acknowledged
+    UploadChunk {
+        upload_id: u32,
+        data: Vec<u8>,
+    },
+    CancelUpload {
+        upload_id: u32,
+    },
+    GetMediaLibrary,
+    /// Adds a player showing media that's already in the library
+    NewMediaFromLibrary {
+        asset_id: String,
+        position: Position,
+        width: i32,
+        height: Option<i32>,
+    },
+    /// Deletes media from the library, along with the players showing it
+    PurgeMedia {
+        asset_id: String,
+    },
+    /// Copies an existing player server-side, so the media doesn't have to be sent again
+    DuplicatePlayer {
+        player_name: String,
+        /// Where the copy is placed relative to the original
+        offset: Position,
+    },
+    /// Applies several player mutations at once, under one lock and with one broadcast
+    Batch(Vec<Message>),
+    GetApiTokens,
+    CreateApiToken {
+        name: String,
+        scope: Scope,
+    },
+    RevokeApiToken {
+        token_id: String,
+    },
+    GetRoles,
+    /// `None` removes the assignment, falling back to what twitch says
+    SetRole {
+        login: String,
+        role: Option<Role>,
+    },
+    /// The newest audit log entries, both filters match case insensitively anywhere in the field
+    GetAuditLog {
+        actor: Option<String>,
+        action: Option<String>,
+    },
+    /// Tells the other editors what this one has selected and where its mouse is
+    UpdatePresence {
+        selection: Vec<String>,
+        cursor: Option<Position>,
+    },
+    /// Sent when a drag or resize starts, players someone else holds aren't granted
+    AcquireLeases {
+        player_names: Vec<String>,
+    },
+    /// Sent when the drag or resize ends, releases every lease of this socket
+    ReleaseLeases,
+    /// A message the sender already applied locally, answered with an [`Event::Ack`] once it's handled
+    Sequenced {
+        /// Picked by the client, counting up per connection
+        seq: u32,
+        message: Box<Message>,
+    },
+    /// Sent when reconnecting, answered with the changes since then followed by an [`Event::Revision`],
+    /// or with [`Event::AllPlayers`] if the server doesn't have all of them anymore
+    GetChangesSince {
+        revision: u64,
+    },
+    GetConnections,
+    /// Disconnects the socket, it's told not to reconnect on its own
+    KickConnection {
+        socket_id: u32,
+    },
+    /// Positions and sizes while dragging or resizing, rapid ones are merged and they aren't recorded,
+    /// the final values follow as a normal message on mouseup
+    DragUpdate(Vec<Message>),
+}
+
+impl Message {
+    /// The role needed to send this, `None` if anyone can
+    pub fn required_role(&self) -> Option<Role> {
+        match self {
+            Message::Ping
+            | Message::Authorize(_)
+            | Message::GetAllPlayers
+            | Message::GetChangesSince { .. } => None,
+            Message::GetMediaLibrary | Message::UpdatePresence { .. } => Some(Role::Viewer),
+            Message::GetAuditLog { .. }
+            | Message::GetConnections
+            | Message::KickConnection { .. } => Some(Role::Moderator),
+            Message::SetPosition { .. }
+            | Message::SetSize { .. }
+            | Message::MovePlayerUp { .. }
+            | Message::MovePlayerDown { .. }
+            | Message::FlipPlayerHorizontally { .. }
+            | Message::AcquireLeases { .. }
+            | Message::ReleaseLeases => Some(Role::Moderator),
+            Message::NewMedia { .. }
+            | Message::DeletePlayer { .. }
+            | Message::NewMediaFromUrl { .. }
+            | Message::StartUpload { .. }
+            | Message::UploadChunk { .. }
+            | Message::CancelUpload { .. }
+            | Message::NewMediaFromLibrary { .. }
+            | Message::PurgeMedia { .. }
+            | Message::DuplicatePlayer { .. } => Some(Role::Editor),
+            Message::GetApiTokens
+            | Message::CreateApiToken { .. }
+            | Message::RevokeApiToken { .. }
+            | Message::GetRoles
+            | Message::SetRole { .. } => Some(Role::Owner),
+            Message::Batch(messages) | Message::DragUpdate(messages) => {
+                messages.iter().filter_map(Message::required_role).max()
+            }
+            Message::Sequenced { message, .. } => message.required_role(),
+        }
+    }
+
+    /// The player this changes, `None` for messages that don't change an existing player
+    pub fn edited_player(&self) -> Option<&str> {
+        match self {
+            Message::SetPosition { player_name, .. }
+            | Message::SetSize { player_name, .. }
+            | Message::DeletePlayer { player_name }
+            | Message::MovePlayerUp { player_name }
+            | Message::MovePlayerDown { player_name }
+            | Message::FlipPlayerHorizontally { player_name, .. } => Some(player_name),
+            _ => None,
+        }
+    }
+
+    /// Every player this changes, including the ones changed by the parts of a batch
+    pub fn edited_players(&self) -> Vec<&str> {
+        match self {
+            Message::Batch(messages) | Message::DragUpdate(messages) => {
+                messages.iter().flat_map(Message::edited_players).collect()
+            }
+            Message::Sequenced { message, .. } => message.edited_players(),
+            message => message.edited_player().into_iter().collect(),
+        }
+    }
 }
 
 /// Events from backend to frontend
+///
+/// Variants are encoded by index, so new ones go at the end, anything else needs a [`PROTOCOL_VERSION`] bump
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ssr", derive(schemars::JsonSchema))]
 pub enum Event {
     Pong,
     AllPlayers(IndexMap<String, ServerPlayer>),
@@ -215,4 +611,75 @@ pub enum Event {
         player_name: String,
         is_flipped: bool,
     },
+    /// The events produced by a [`Message::Batch`], in the order they were applied
+    Batch(Vec<Event>),
+    MediaLibrary(Vec<MediaAsset>),
+    MediaAdded(MediaAsset),
+    MediaPurged {
+        asset_id: String,
+    },
+    /// How much of an upload the server received, the client sends the next chunk after this
+    UploadProgress {
+        upload_id: u32,
+        received: u64,
+    },
+    /// The upload was cancelled by the server, e.g. for being too large
+    UploadRejected {
+        upload_id: u32,
+        reason: String,
+    },
+    /// New media couldn't be added, only sent to the client that tried to add it
+    MediaRejected {
+        name: String,
+        reason: String,
+    },
+    /// Only sent to admins that asked for them or changed them
+    ApiTokens(Vec<ApiToken>),
+    /// Only sent to the admin that created the token, the secret can't be shown again
+    ApiTokenCreated {
+        token: ApiToken,
+        secret: String,
+    },
+    /// Sent after a successful [`Message::Authorize`]
+    Authorized {
+        role: Role,
+    },
+    /// Roles the owner assigned to twitch logins, only sent to the owner
+    Roles(IndexMap<String, Role>),
+    /// Answer to [`Message::GetAuditLog`], newest first
+    AuditLog(Vec<AuditEntry>),
+    /// Everyone else on the control page, sent whenever someone joins or leaves
+    Presence(Vec<Presence>),
+    /// Someone changed their selection or moved their mouse
+    PresenceUpdated(Presence),
+    /// Players held by someone else by name, sent whenever a lease is granted, released or expires
+    Leases(IndexMap<String, Lease>),
+    /// Answer to a [`Message::Sequenced`], with what the server has for the players it changed
+    Ack {
+        seq: u32,
+        events: Vec<Event>,
+    },
+    /// The revision the players are at, sent to everyone after every change and after [`Event::AllPlayers`]
+    Revision(u64),
+    /// Answer to [`Message::GetConnections`] and [`Message::KickConnection`], oldest connection first
+    Connections(Vec<ConnectionInfo>),
+}
+
+impl Event {
+    /// Whether this changes the players, which gets it a revision
+    pub fn changes_players(&self) -> bool {
+        match self {
+            // only broadcast when a scene replaces every player
+            Event::AllPlayers(_)
+            | Event::NewPlayer(_)
+            | Event::PositionUpdated { .. }
+            | Event::SizeUpdated { .. }
+            | Event::PlayerDeleted { .. }
+            | Event::PlayerMovedDown { .. }
+            | Event::PlayerMovedUp { .. }
+            | Event::FlipPlayerHorizontally { .. } => true,
+            Event::Batch(events) => events.iter().any(Event::changes_players),
+            _ => false,
+        }
+    }
 }
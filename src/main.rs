@@ -1,5 +1,5 @@
 use indexmap::IndexMap;
-use strim_overlay::server::ssr::websocket;
+use strim_overlay::server::ssr::{expire_leases, schema, websocket};
 use tower_http::compression::CompressionLayer;
 
 cfg_if::cfg_if! {
@@ -10,8 +10,10 @@ cfg_if::cfg_if! {
         };
         use strim_overlay::shell;
         use leptos_axum::{generate_route_list, LeptosRoutes};
-        use leptos::config::get_configuration;
-        use strim_overlay::{AppState};
+        use leptos::{config::get_configuration, prelude::provide_context};
+        use strim_overlay::{AppState, api, audit::AuditLog, connections::ConnectionRegistry, leases::LeaseStore, media::{MEDIA_DIR, MediaLibrary}, revisions::Broadcaster, roles::RoleStore, scenes::SceneStore, tokens::TokenStore};
+        use tower_http::{services::ServeDir, set_header::SetResponseHeader};
+        use axum::http::{HeaderValue, header};
 
         #[tokio::main]
         async fn main() {
@@ -20,17 +22,45 @@ cfg_if::cfg_if! {
             let addr = leptos_options.site_addr;
             let routes = generate_route_list(strim_overlay::app::App);
 
-            let (sender, _receiver) = tokio::sync::broadcast::channel::<(u32, strim_overlay::Event)>(1024);
+            let max_upload_size = std::env::var("MAX_UPLOAD_SIZE_MB")
+                .ok()
+                .and_then(|size| size.parse::<usize>().ok())
+                .unwrap_or(100)
+                * 1024
+                * 1024;
 
             let state = AppState {
                 leptos_options,
                 players: std::sync::Arc::new(tokio::sync::RwLock::new(IndexMap::new())),
-                broadcaster: sender,
+                broadcaster: Broadcaster::new(1024),
+                media_library: std::sync::Arc::new(tokio::sync::RwLock::new(MediaLibrary::load().await)),
+                tokens: std::sync::Arc::new(tokio::sync::RwLock::new(TokenStore::load().await)),
+                roles: std::sync::Arc::new(tokio::sync::RwLock::new(RoleStore::load().await)),
+                audit_log: std::sync::Arc::new(tokio::sync::RwLock::new(AuditLog::load().await)),
+                scenes: std::sync::Arc::new(tokio::sync::RwLock::new(SceneStore::load().await)),
+                presence: std::sync::Arc::new(tokio::sync::RwLock::new(IndexMap::new())),
+                leases: std::sync::Arc::new(tokio::sync::RwLock::new(LeaseStore::default())),
+                connections: std::sync::Arc::new(tokio::sync::RwLock::new(ConnectionRegistry::default())),
+                max_upload_size,
             };
 
+            tokio::spawn(expire_leases(state.clone()));
+
             let app = Router::new()
                 .route("/ws", get(websocket))
-                 .leptos_routes(&state, routes, {
+                .route("/ws/schema.json", get(schema))
+                .merge(api::router())
+                // svgs stored before they were refused could still run scripts when opened directly
+                .nest_service(&format!("/{MEDIA_DIR}"), SetResponseHeader::overriding(
+                    ServeDir::new(MEDIA_DIR),
+                    header::CONTENT_SECURITY_POLICY,
+                    HeaderValue::from_static("sandbox"),
+                ))
+                 .leptos_routes_with_context(&state, routes, {
+                    // server functions like log_in need the roles
+                    let state = state.clone();
+                    move || provide_context(state.clone())
+                }, {
                     let leptos_options = state.leptos_options.clone();
                     move || shell(leptos_options.clone())
                 })
@@ -40,7 +70,7 @@ cfg_if::cfg_if! {
 
             let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
             tracing::info!("listening on http://{}", &addr);
-            axum::serve(listener, app.into_make_service())
+            axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
                 .await
                 .unwrap();
         }
@@ -0,0 +1,440 @@
+//! The media library, uploaded and fetched media stored on disk independently of the players showing it
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use indexmap::IndexMap;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use tokio::sync::RwLock;
+
+use crate::{MediaAsset, MediaType, hashing::hash, json_file};
+
+/// Where the library is stored, the media is served under the same path
+pub const MEDIA_DIR: &str = "media";
+const LIBRARY_INDEX: &str = "library.json";
+/// Thumbnails fit into a square of this many pixels
+const THUMBNAIL_SIZE: u32 = 160;
+/// How long ffmpeg gets to take a video's poster
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(30);
+/// Redirects followed when fetching a link, each one is checked like the link itself
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MediaError {
+    #[error("invalid link: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("only http and https links are supported")]
+    UnsupportedScheme,
+    #[error("couldn't resolve the link: {0}")]
+    Resolve(std::io::Error),
+    #[error("links to private addresses aren't allowed")]
+    PrivateAddress,
+    #[error("the link redirects too often")]
+    TooManyRedirects,
+    #[error("can't delete it while {holder} is editing {player_name}")]
+    Leased { player_name: String, holder: String },
+    #[error("unsupported media type {0:?}")]
+    UnsupportedType(String),
+    #[error("media is bigger than the {} MB limit", .limit / 1024 / 1024)]
+    TooLarge { limit: usize },
+    #[error("media is empty")]
+    Empty,
+    #[error("media isn't a base64 data url")]
+    InvalidDataUrl,
+    #[error("invalid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("couldn't store media: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Default)]
+pub struct MediaLibrary {
+    assets: IndexMap<String, MediaAsset>,
+}
+
+impl MediaLibrary {
+    /// Loads the library index from [`MEDIA_DIR`], starting empty if there is none
+    pub async fn load() -> Self {
+        let mut library = Self {
+            assets: json_file::load(index_path(), "media library index").await,
+        };
+        library.generate_missing_thumbnails().await;
+
+        library
+    }
+
+    /// Media stored before thumbnails existed used the full image, or nothing for videos
+    async fn generate_missing_thumbnails(&mut self) {
+        let mut changed = false;
+        for asset in self.assets.values_mut() {
+            if asset
+                .thumbnail
+                .as_ref()
+                .is_some_and(|thumbnail| *thumbnail != asset.src)
+            {
+                continue;
+            }
+            let Some(file_name) = asset.src.rsplit('/').next() else {
+                continue;
+            };
+            let thumbnail = generate_thumbnail(&asset.id, asset.media_type, file_name).await;
+            if thumbnail.is_some() && thumbnail != asset.thumbnail {
+                asset.thumbnail = thumbnail;
+                changed = true;
+            }
+        }
+
+        if changed && let Err(e) = self.save().await {
+            tracing::error!("couldn't save the media library index: {e}");
+        }
+    }
+
+    async fn save(&self) -> Result<(), MediaError> {
+        tokio::fs::create_dir_all(MEDIA_DIR).await?;
+        json_file::save(index_path(), &self.assets).await?;
+
+        Ok(())
+    }
+
+    pub fn assets(&self) -> Vec<MediaAsset> {
+        self.assets.values().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MediaAsset> {
+        self.assets.get(id)
+    }
+
+    /// Whether `src` is where one of the assets is served from
+    pub fn serves(&self, src: &str) -> bool {
+        self.assets.values().any(|asset| asset.src == src)
+    }
+
+    /// Removes the asset and its file from the library
+    pub async fn purge(&mut self, id: &str) -> Result<Option<MediaAsset>, MediaError> {
+        let Some(asset) = self.assets.shift_remove(id) else {
+            return Ok(None);
+        };
+
+        for src in std::iter::once(&asset.src).chain(asset.thumbnail.as_ref()) {
+            let Some(file_name) = src.rsplit('/').next() else {
+                continue;
+            };
+            match tokio::fs::remove_file(Path::new(MEDIA_DIR).join(file_name)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        self.save().await?;
+
+        Ok(Some(asset))
+    }
+}
+
+/// Stores a finished chunked upload, identical uploads are only stored once
+pub async fn store_upload(
+    library: &RwLock<MediaLibrary>,
+    name: String,
+    content_type: &str,
+    bytes: &[u8],
+) -> Result<(MediaAsset, bool), MediaError> {
+    store(library, hash(bytes), name, content_type, bytes).await
+}
+
+/// Stores media uploaded as a base64 `data:` url, identical uploads are only stored once
+pub async fn store_data_url(
+    library: &RwLock<MediaLibrary>,
+    name: String,
+    data_url: &str,
+) -> Result<(MediaAsset, bool), MediaError> {
+    let (content_type, payload) = data_url
+        .strip_prefix("data:")
+        .and_then(|data_url| data_url.split_once(";base64,"))
+        .ok_or(MediaError::InvalidDataUrl)?;
+    let bytes = base64::engine::general_purpose::STANDARD.decode(payload)?;
+
+    store(library, hash(&bytes), name, content_type, &bytes).await
+}
+
+/// Stores `bytes` under `id`, returning the asset and whether it wasn't in the library before
+///
+/// The library is only locked to look up and add the asset, writing the file and its thumbnail
+/// can take a while
+async fn store(
+    library: &RwLock<MediaLibrary>,
+    id: String,
+    name: String,
+    content_type: &str,
+    bytes: &[u8],
+) -> Result<(MediaAsset, bool), MediaError> {
+    if let Some(asset) = library.read().await.get(&id) {
+        return Ok((asset.clone(), false));
+    }
+
+    let Some((media_type, extension)) = MediaType::of_content_type(content_type) else {
+        return Err(MediaError::UnsupportedType(content_type.to_string()));
+    };
+
+    let file_name = format!("{id}.{extension}");
+    tokio::fs::create_dir_all(MEDIA_DIR).await?;
+    tokio::fs::write(Path::new(MEDIA_DIR).join(&file_name), bytes).await?;
+
+    let src = format!("/{MEDIA_DIR}/{file_name}");
+    let asset = MediaAsset {
+        thumbnail: generate_thumbnail(&id, media_type, &file_name).await,
+        id: id.clone(),
+        name,
+        media_type,
+        size: bytes.len() as u64,
+        src,
+        uploaded_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+
+    let mut library = library.write().await;
+    // the same media could have been stored in the meantime, it's the same file either way
+    if let Some(asset) = library.assets.get(&id) {
+        return Ok((asset.clone(), false));
+    }
+    library.assets.insert(id, asset.clone());
+    library.save().await?;
+
+    Ok((asset, true))
+}
+
+/// Downloads `url` into the library, media already in it is only stored once
+///
+/// The library is only locked after the download, so a slow site doesn't block everyone else
+pub async fn fetch_remote_media(
+    library: &RwLock<MediaLibrary>,
+    url: &str,
+    max_size: usize,
+) -> Result<(MediaAsset, bool), MediaError> {
+    let url = url::Url::parse(url)?;
+    check_scheme(&url)?;
+
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .or(url.host_str())
+        .unwrap_or("media")
+        .to_string();
+
+    let mut response = follow_redirects(url).await?.error_for_status()?;
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    if !is_supported(&content_type) {
+        return Err(MediaError::UnsupportedType(content_type));
+    }
+
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size as u64)
+    {
+        return Err(MediaError::TooLarge { limit: max_size });
+    }
+
+    // the content length can be missing or wrong, so check while downloading too
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > max_size {
+            return Err(MediaError::TooLarge { limit: max_size });
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    store(library, hash(&bytes), name, &content_type, &bytes).await
+}
+
+/// Checks that `link` is an http or https link, without fetching it
+pub fn check_link(link: &str) -> Result<(), MediaError> {
+    check_scheme(&url::Url::parse(link)?)
+}
+
+fn check_scheme(url: &url::Url) -> Result<(), MediaError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(MediaError::UnsupportedScheme);
+    }
+
+    Ok(())
+}
+
+/// Requests `url`, checking every redirect like the link itself instead of letting reqwest follow them
+async fn follow_redirects(mut url: url::Url) -> Result<reqwest::Response, MediaError> {
+    for _ in 0..=MAX_REDIRECTS {
+        let address = public_address(&url).await?;
+        let mut client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .redirect(reqwest::redirect::Policy::none());
+        // connecting to the checked address keeps a second lookup from pointing somewhere else
+        if let Some(url::Host::Domain(domain)) = url.host() {
+            client = client.resolve(domain, address);
+        }
+        let response = client.build()?.get(url.clone()).send().await?;
+        if !response.status().is_redirection() {
+            return Ok(response);
+        }
+
+        let Some(location) = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+        else {
+            return Ok(response);
+        };
+        url = url.join(location)?;
+        check_scheme(&url)?;
+    }
+
+    Err(MediaError::TooManyRedirects)
+}
+
+/// Resolves the link's host, refusing it if any of its addresses is inside the server's network
+async fn public_address(url: &url::Url) -> Result<SocketAddr, MediaError> {
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses = match url.host() {
+        Some(url::Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(MediaError::Resolve)?
+            .collect(),
+        Some(url::Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(url::Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        None => return Err(url::ParseError::EmptyHost.into()),
+    };
+
+    if !addresses.iter().all(|address| is_public(address.ip())) {
+        return Err(MediaError::PrivateAddress);
+    }
+
+    addresses
+        .into_iter()
+        .next()
+        .ok_or(MediaError::Resolve(std::io::ErrorKind::NotFound.into()))
+}
+
+/// Whether the address is reachable from the internet, anything else could be the server itself,
+/// its network or a cloud metadata service
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                // includes the 169.254.169.254 metadata services
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || first == 0
+                // shared address space of carrier-grade nat
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                let first_segment = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local addresses
+                    || first_segment & 0xfe00 == 0xfc00
+                    // link local addresses
+                    || first_segment & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Writes a small png preview next to the media, returning where it's served from
+///
+/// Video posters need ffmpeg to be installed
+async fn generate_thumbnail(id: &str, media_type: MediaType, file_name: &str) -> Option<String> {
+    let source = Path::new(MEDIA_DIR).join(file_name);
+    let thumbnail_name = format!("{id}.thumb.png");
+    let destination = Path::new(MEDIA_DIR).join(&thumbnail_name);
+
+    let generated = match media_type {
+        MediaType::Image => {
+            let result = tokio::task::spawn_blocking(move || {
+                image::open(source)?
+                    .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+                    .save(destination)
+            })
+            .await;
+            match result {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    tracing::warn!("couldn't generate a thumbnail for {file_name}: {e}");
+                    false
+                }
+                Err(e) => {
+                    tracing::error!("thumbnail generation for {file_name} panicked: {e}");
+                    false
+                }
+            }
+        }
+        MediaType::Video => {
+            let command = tokio::process::Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-i"])
+                .arg(&source)
+                .args([
+                    "-frames:v",
+                    "1",
+                    "-vf",
+                    &format!(
+                        "scale={THUMBNAIL_SIZE}:{THUMBNAIL_SIZE}:force_original_aspect_ratio=decrease"
+                    ),
+                ])
+                .arg(&destination)
+                // a timed out ffmpeg is dropped, which shouldn't leave it running
+                .kill_on_drop(true)
+                .output();
+            match tokio::time::timeout(FFMPEG_TIMEOUT, command).await {
+                Ok(Ok(output)) if output.status.success() => true,
+                Ok(Ok(output)) => {
+                    tracing::warn!(
+                        "couldn't generate a poster for {file_name}: {}",
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                    false
+                }
+                // not having ffmpeg installed is fine, videos just don't get a poster
+                Ok(Err(e)) => {
+                    tracing::debug!("couldn't run ffmpeg for {file_name}: {e}");
+                    false
+                }
+                Err(_) => {
+                    tracing::warn!("ffmpeg took longer than {FFMPEG_TIMEOUT:?} for {file_name}");
+                    false
+                }
+            }
+        }
+        MediaType::Text => false,
+    };
+
+    generated.then(|| format!("/{MEDIA_DIR}/{thumbnail_name}"))
+}
+
+pub fn is_supported(content_type: &str) -> bool {
+    MediaType::of_content_type(content_type).is_some()
+}
+
+fn index_path() -> PathBuf {
+    Path::new(MEDIA_DIR).join(LIBRARY_INDEX)
+}
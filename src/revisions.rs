@@ -0,0 +1,224 @@
+//! Numbered changes of the players, so clients that missed some can catch up without a full resync
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::broadcast;
+
+use crate::Event;
+
+/// Most changes kept, clients further behind get every player again
+const KEPT_CHANGES: usize = 1000;
+
+#[derive(Debug)]
+struct Revisions {
+    revision: u64,
+    changes: VecDeque<(u64, Event)>,
+}
+
+impl Revisions {
+    fn new() -> Self {
+        // starting at the startup time in microseconds keeps revisions from before a restart older
+        // than the current ones, so they can't be mistaken for them
+        let revision = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        Self {
+            revision,
+            changes: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, event: Event) -> u64 {
+        self.revision += 1;
+        self.changes.push_back((self.revision, event));
+        if self.changes.len() > KEPT_CHANGES {
+            self.changes.pop_front();
+        }
+
+        self.revision
+    }
+
+    fn since(&self, revision: u64) -> Option<Vec<Event>> {
+        if revision > self.revision {
+            return None;
+        }
+        let first_missed = revision + 1;
+        if self
+            .changes
+            .front()
+            .is_some_and(|(oldest, _)| *oldest > first_missed)
+            || (self.changes.is_empty() && revision != self.revision)
+        {
+            return None;
+        }
+
+        Some(
+            self.changes
+                .iter()
+                .filter(|(changed_at, _)| *changed_at > revision)
+                .map(|(_, event)| event.clone())
+                .collect(),
+        )
+    }
+}
+
+/// An event on its way to every socket
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    /// The socket it came from, which skips it
+    pub sender_id: u32,
+    pub event: Event,
+    /// Set for changes of the players, the sockets pass it on as an [`Event::Revision`]
+    pub revision: Option<u64>,
+}
+
+/// The channel every event goes through, changes of the players are numbered on the way
+#[derive(Debug, Clone)]
+pub struct Broadcaster {
+    sender: broadcast::Sender<Broadcast>,
+    revisions: Arc<Mutex<Revisions>>,
+}
+
+impl Broadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _receiver) = broadcast::channel(capacity);
+
+        Self {
+            sender,
+            revisions: Arc::new(Mutex::new(Revisions::new())),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Broadcast> {
+        self.sender.subscribe()
+    }
+
+    /// Returns how many sockets got the event, `None` if there are none
+    pub fn send(&self, (sender_id, event): (u32, Event)) -> Option<usize> {
+        if !event.changes_players() {
+            return self
+                .sender
+                .send(Broadcast {
+                    sender_id,
+                    event,
+                    revision: None,
+                })
+                .ok();
+        }
+
+        // numbering and sending under one lock keeps revisions in the order clients get them
+        let mut revisions = self
+            .revisions
+            .lock()
+            .expect("revisions lock shouldn't be poisoned");
+        let revision = revisions.record(event.clone());
+        self.sender
+            .send(Broadcast {
+                sender_id,
+                event,
+                revision: Some(revision),
+            })
+            .ok()
+    }
+
+    /// The revision of the players right now
+    pub fn revision(&self) -> u64 {
+        self.revisions
+            .lock()
+            .expect("revisions lock shouldn't be poisoned")
+            .revision
+    }
+
+    /// The changes made after `revision`, `None` if some of them aren't kept anymore
+    pub fn changes_since(&self, revision: u64) -> Option<Vec<Event>> {
+        self.revisions
+            .lock()
+            .expect("revisions lock shouldn't be poisoned")
+            .since(revision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 100;
+
+    fn revisions() -> Revisions {
+        Revisions {
+            revision: START,
+            changes: VecDeque::new(),
+        }
+    }
+
+    fn deleted(index: usize) -> Event {
+        Event::PlayerDeleted {
+            player_name: index.to_string(),
+        }
+    }
+
+    fn deleted_names(events: Vec<Event>) -> Vec<String> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                Event::PlayerDeleted { player_name } => player_name,
+                event => panic!("unexpected event {event:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn numbers_changes_in_order() {
+        let mut revisions = revisions();
+
+        assert_eq!(revisions.record(deleted(0)), START + 1);
+        assert_eq!(revisions.record(deleted(1)), START + 2);
+        assert_eq!(deleted_names(revisions.since(START).unwrap()), ["0", "1"]);
+        assert_eq!(deleted_names(revisions.since(START + 1).unwrap()), ["1"]);
+    }
+
+    #[test]
+    fn nothing_changed_since_the_current_revision() {
+        let mut revisions = revisions();
+        assert!(revisions.since(START).unwrap().is_empty());
+
+        revisions.record(deleted(0));
+        assert!(revisions.since(START + 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn older_revisions_without_changes_are_unknown() {
+        // a restart forgets every change, so a revision from before it can't be caught up
+        assert!(revisions().since(START - 1).is_none());
+    }
+
+    #[test]
+    fn future_revisions_are_unknown() {
+        let mut revisions = revisions();
+        assert!(revisions.since(START + 1).is_none());
+
+        revisions.record(deleted(0));
+        assert!(revisions.since(START + 2).is_none());
+        assert!(revisions.since(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn catches_up_exactly_at_the_oldest_kept_change() {
+        let mut revisions = revisions();
+        for index in 0..=KEPT_CHANGES {
+            revisions.record(deleted(index));
+        }
+
+        // the first change was dropped, so only the revision it made can still catch up
+        let changes = revisions.since(START + 1).unwrap();
+        assert_eq!(changes.len(), KEPT_CHANGES);
+        assert_eq!(deleted_names(changes)[0], "1");
+        assert!(revisions.since(START).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! Roles the owner assigned to twitch logins, overriding what twitch says about them
+
+use indexmap::IndexMap;
+
+use crate::{
+    Role,
+    auth::{CHANNEL, User},
+    json_file,
+};
+
+/// Where the assigned roles are stored
+pub const ROLES_FILE: &str = "roles.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoleError {
+    #[error("the login is empty")]
+    EmptyLogin,
+    #[error("couldn't save roles: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Default)]
+pub struct RoleStore {
+    roles: IndexMap<String, Role>,
+}
+
+impl RoleStore {
+    /// Loads the roles from [`ROLES_FILE`], starting empty if there is none
+    pub async fn load() -> Self {
+        let roles: IndexMap<String, Role> = json_file::load(ROLES_FILE, "roles").await;
+        // roles assigned before logins were normalized could be in any case
+        Self {
+            roles: roles
+                .into_iter()
+                .map(|(login, role)| (normalize(&login), role))
+                .filter(|(login, _)| !login.is_empty())
+                .collect(),
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        json_file::save(ROLES_FILE, &self.roles).await
+    }
+
+    pub fn roles(&self) -> IndexMap<String, Role> {
+        self.roles.clone()
+    }
+
+    /// The broadcaster is always the owner, everyone else gets their assigned role,
+    /// moderators without one are moderators and anyone else has no access
+    pub fn role_of(&self, user: &User) -> Option<Role> {
+        if user.login == CHANNEL {
+            return Some(Role::Owner);
+        }
+
+        self.roles
+            .get(&user.login)
+            .copied()
+            .or(user.is_moderator.then_some(Role::Moderator))
+    }
+
+    /// Assigns `role` to `login`, `None` removes the assignment
+    pub async fn set(&mut self, login: String, role: Option<Role>) -> Result<(), RoleError> {
+        let login = normalize(&login);
+        if login.is_empty() {
+            return Err(RoleError::EmptyLogin);
+        }
+        match role {
+            Some(role) => {
+                self.roles.insert(login, role);
+            }
+            None => {
+                self.roles.shift_remove(&login);
+            }
+        }
+
+        Ok(self.save().await?)
+    }
+}
+
+/// Twitch logins are lowercase, but people type them however they like
+fn normalize(login: &str) -> String {
+    login.trim().to_lowercase()
+}
@@ -0,0 +1,53 @@
+//! Named snapshots of every player, so a script can switch the overlay to another layout at once
+
+use indexmap::IndexMap;
+
+use crate::{ServerPlayer, json_file};
+
+/// Where the scenes are stored
+pub const SCENES_FILE: &str = "scenes.json";
+
+/// The players of a scene by name, from the bottom to the top like [`crate::AppState::players`]
+pub type Scene = IndexMap<String, ServerPlayer>;
+
+#[derive(Debug, Default)]
+pub struct SceneStore {
+    scenes: IndexMap<String, Scene>,
+}
+
+impl SceneStore {
+    /// Loads the scenes from [`SCENES_FILE`], starting empty if there is none
+    pub async fn load() -> Self {
+        Self {
+            scenes: json_file::load(SCENES_FILE, "scenes").await,
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        json_file::save(SCENES_FILE, &self.scenes).await
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.scenes.keys().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Scene> {
+        self.scenes.get(name)
+    }
+
+    /// Stores `players` as the scene `name`, replacing a scene with the same name
+    pub async fn set(&mut self, name: String, players: Scene) -> std::io::Result<()> {
+        self.scenes.insert(name, players);
+        self.save().await
+    }
+
+    /// Returns whether there was a scene named `name`
+    pub async fn remove(&mut self, name: &str) -> std::io::Result<bool> {
+        if self.scenes.shift_remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save().await?;
+
+        Ok(true)
+    }
+}
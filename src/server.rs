@@ -1,336 +1,1367 @@
 use leptos::prelude::*;
 
+/// Checks the twitch access token and returns a session for the websocket, so twitch isn't asked again on reconnects
 #[server]
-pub async fn is_authorized(access_token: String) -> Result<bool, ServerFnError> {
-    use super::server::ssr::TwitchResponse;
-
-    let client = reqwest::Client::new();
-    let res = client
-        .get("https://api.twitch.tv/helix/users")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Client-Id", "48mas39k4vcamtq5fy33r7qegf13l9")
-        .send()
-        .await?
-        .json::<TwitchResponse>()
-        .await?;
-
-    let data = &res.data[0];
-
-    if data.get("login").unwrap().as_str().unwrap() == "sadmadladsalman" {
-        return Ok(true);
-    }
-
-    let id = data.get("id").unwrap().as_str().unwrap();
-
-    let res = client
-        .get(format!(
-            "https://api.twitch.tv/helix/moderation/channels?user_id={}&first=100",
-            id
-        ))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Client-Id", "48mas39k4vcamtq5fy33r7qegf13l9")
-        .send()
-        .await?
-        .json::<TwitchResponse>()
-        .await?;
-
-    Ok(res
-        .data
-        .iter()
-        .any(|broadcaster| broadcaster.get("broadcaster_login").unwrap() == "sadmadladsalman"))
+pub async fn log_in(access_token: String) -> Result<String, ServerFnError> {
+    use crate::{
+        AppState,
+        auth::{AuthError, check_twitch_token, create_session},
+    };
+
+    let state = use_context::<AppState>().ok_or_else(|| ServerFnError::new("missing app state"))?;
+    let user = check_twitch_token(&access_token)
+        .await
+        .map_err(ServerFnError::new)?;
+    if state.roles.read().await.role_of(&user).is_none() {
+        return Err(ServerFnError::new(AuthError::NoRole(user.login)));
+    }
+
+    Ok(create_session(&user))
+}
+
+/// Every audit log entry matching the filters as json lines, oldest first, the control page only
+/// gets the newest page of them over the websocket
+#[server]
+pub async fn export_audit_log(
+    access_token: String,
+    actor: Option<String>,
+    action: Option<String>,
+) -> Result<String, ServerFnError> {
+    use crate::{
+        AppState, Role,
+        audit::{self, Filter},
+        auth::Identity,
+    };
+
+    let state = use_context::<AppState>().ok_or_else(|| ServerFnError::new("missing app state"))?;
+    let identity = Identity::authorize(&state, access_token)
+        .await
+        .map_err(ServerFnError::new)?;
+    if identity.role(&state).await < Some(Role::Moderator) {
+        return Err(ServerFnError::new(
+            "exporting the audit log needs the Moderator role",
+        ));
+    }
+
+    audit::export(&Filter::new(actor.as_deref(), action.as_deref()))
+        .await
+        .map_err(ServerFnError::new)
 }
 
 #[cfg(feature = "ssr")]
 pub mod ssr {
-    use crate::{Event, MediaType, Message as OverlayMessage, ServerPlayer, server::is_authorized};
-    use axum::extract::{State, ws::Message};
+    use crate::{
+        Event, JSON_SUBPROTOCOL, KICKED_CLOSE_CODE, MAX_UPLOADS_PER_SOCKET, MIN_PLAYER_SIZE,
+        MediaAsset, MediaType, Message as OverlayMessage, PROTOCOL_VERSION, Presence,
+        RELOAD_CLOSE_CODE, Role, ServerPlayer, UPLOAD_CHUNK_SIZE,
+        audit::{self, Filter},
+        auth::Identity,
+        leases::EXPIRY_INTERVAL,
+        media::{MediaError, fetch_remote_media, is_supported, store_data_url, store_upload},
+        revisions::{Broadcast, Broadcaster},
+    };
+    use axum::extract::{
+        ConnectInfo, Query, State,
+        ws::{CloseFrame, Message},
+    };
     use indexmap::IndexMap;
     use leptos::*;
     use serde::Deserialize;
+    use std::{collections::HashMap, mem::Discriminant, net::SocketAddr, time::Duration};
+    use tokio::{
+        sync::{broadcast::error::RecvError, mpsc},
+        time::Instant,
+    };
 
-    #[derive(Debug, Deserialize)]
-    pub(crate) struct TwitchResponse {
-        pub data: Vec<serde_json::Map<String, serde_json::Value>>,
+    use crate::AppState;
+
+    /// Sender id of events no socket caused, so every socket gets them
+    pub(crate) const NO_SENDER_ID: u32 = u32::MAX;
+    /// How long drag updates are collected before applying the latest one of each player
+    const DRAG_UPDATE_INTERVAL: Duration = Duration::from_millis(30);
+    /// How often every socket is pinged, browsers answer pings on their own
+    const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+    /// Sockets nothing arrived from for this long are closed, the other end is most likely gone
+    const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+    /// Sends taking longer than this give up, a half-open socket never takes anything
+    const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The latest drag update for each player and kind of change
+    type DragUpdates = IndexMap<(String, Discriminant<OverlayMessage>), OverlayMessage>;
+
+    /// A file being uploaded in chunks, the player is only added once it's complete
+    struct PendingUpload {
+        name: String,
+        content_type: String,
+        size: u64,
+        position: crate::Position,
+        width: i32,
+        height: Option<i32>,
+        bytes: Vec<u8>,
     }
 
-    use crate::AppState;
+    /// Media stored in the background, fetching links and making thumbnails can take longer than
+    /// a socket may go unread, the player is added back in the socket's handler
+    enum StoredMedia {
+        /// A [`OverlayMessage::NewMedia`] with a data url, from clients before chunked uploads
+        DataUrl {
+            name: String,
+            media_type: MediaType,
+            position: crate::Position,
+            width: i32,
+            height: Option<i32>,
+            stored: Result<MediaAsset, MediaError>,
+        },
+        Link {
+            url: String,
+            position: crate::Position,
+            width: i32,
+            height: Option<i32>,
+            stored: Result<MediaAsset, MediaError>,
+        },
+        /// The upload's bytes are dropped once they're stored
+        Upload {
+            upload_id: u32,
+            upload: PendingUpload,
+            stored: Result<MediaAsset, MediaError>,
+        },
+    }
 
-    fn next_id() -> u32 {
-        static mut CURRENT_ID: u32 = 0;
+    /// Wire format of a connection, picked by the subprotocol the client asked for
+    #[derive(Debug, Clone, Copy)]
+    enum Encoding {
+        Bincode,
+        Json,
+    }
 
-        unsafe {
-            let current_id = CURRENT_ID;
+    /// A client's socket, encoding events the way that client understands
+    struct Connection {
+        socket: axum::extract::ws::WebSocket,
+        encoding: Encoding,
+        /// A send timed out, the socket might be half-written and should be closed
+        is_stalled: bool,
+    }
 
-            CURRENT_ID += 1;
+    impl Connection {
+        async fn send_event(&mut self, event: &Event) {
+            let message = match self.encoding {
+                Encoding::Bincode => Message::Binary(bincode::serialize(event).unwrap().into()),
+                Encoding::Json => Message::Text(serde_json::to_string(event).unwrap().into()),
+            };
+            self.send(message).await;
+        }
 
-            return current_id;
+        async fn send(&mut self, message: Message) {
+            if self.is_stalled {
+                return;
+            }
+            if tokio::time::timeout(SEND_TIMEOUT, self.socket.send(message))
+                .await
+                .is_err()
+            {
+                self.is_stalled = true;
+            }
         }
+
+        /// Returns `None` for frames that aren't messages, like pings
+        fn decode(&self, message: &Message) -> Option<Result<OverlayMessage, String>> {
+            match (self.encoding, message) {
+                (Encoding::Bincode, Message::Binary(bytes)) => {
+                    Some(bincode::deserialize(bytes).map_err(|e| e.to_string()))
+                }
+                (Encoding::Json, Message::Text(text)) => {
+                    Some(serde_json::from_str(text.as_str()).map_err(|e| e.to_string()))
+                }
+                (_, Message::Binary(_) | Message::Text(_)) => Some(Err(
+                    "frame type doesn't match the negotiated encoding".to_string(),
+                )),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct WebsocketParams {
+        /// Missing for clients from before the protocol was versioned
+        #[serde(rename = "v")]
+        protocol_version: Option<u32>,
     }
 
     pub async fn websocket(
         State(state): State<AppState>,
+        Query(params): Query<WebsocketParams>,
+        ConnectInfo(remote_address): ConnectInfo<SocketAddr>,
         ws: axum::extract::WebSocketUpgrade,
     ) -> axum::response::Response {
-        ws.on_upgrade(move |ws| handle_socket(ws, state))
+        // uploads are chunked, so nothing legitimate comes close to this except old clients sending data urls
+        let max_message_size = (state.max_upload_size * 4 / 3 + 1024).max(4 * UPLOAD_CHUNK_SIZE);
+        let ws = ws
+            .max_message_size(max_message_size)
+            .protocols([JSON_SUBPROTOCOL]);
+
+        if params.protocol_version != Some(PROTOCOL_VERSION) {
+            return ws.on_upgrade(move |ws| reload_stale_client(ws, params.protocol_version));
+        }
+
+        let encoding = if ws.selected_protocol().is_some() {
+            Encoding::Json
+        } else {
+            Encoding::Bincode
+        };
+        ws.on_upgrade(move |socket| {
+            let socket = Connection {
+                socket,
+                encoding,
+                is_stalled: false,
+            };
+            handle_socket(socket, state, remote_address)
+        })
+    }
+
+    /// Json schema of [`OverlayMessage`] and [`Event`], for tools using [`JSON_SUBPROTOCOL`]
+    pub async fn schema() -> axum::Json<serde_json::Value> {
+        axum::Json(serde_json::json!({
+            "protocol_version": PROTOCOL_VERSION,
+            "subprotocol": JSON_SUBPROTOCOL,
+            "message": schemars::schema_for!(OverlayMessage),
+            "event": schemars::schema_for!(Event),
+        }))
+    }
+
+    /// Closes the socket with [`RELOAD_CLOSE_CODE`], a close frame is the only thing every version understands
+    async fn reload_stale_client(mut socket: axum::extract::ws::WebSocket, version: Option<u32>) {
+        logging::log!("client with protocol version {version:?} connected, asking it to reload");
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code: RELOAD_CLOSE_CODE,
+                reason: format!("protocol version {PROTOCOL_VERSION} required, reload the page")
+                    .into(),
+            })))
+            .await;
     }
 
-    async fn handle_socket(mut socket: axum::extract::ws::WebSocket, state: AppState) {
-        let socket_id = next_id();
+    async fn handle_socket(mut socket: Connection, state: AppState, remote_address: SocketAddr) {
+        let (socket_id, mut kicked) = state.connections.write().await.register(remote_address);
         let mut broadcast_receiver = state.broadcaster.subscribe();
-        let mut authorized = false;
+        // the role is looked up again for every message, so changing it takes effect right away
+        let mut identity: Option<Identity> = None;
+        let mut uploads: HashMap<u32, PendingUpload> = HashMap::new();
+        // completed uploads still being stored, they count towards the uploads a socket can have going
+        let mut storing_uploads = 0;
+        let (stored_media_sender, mut stored_media_receiver) = mpsc::unbounded_channel();
+        // sequence number and changed players of the last sequenced message
+        let mut pending_ack: Option<(u32, Vec<String>)> = None;
+        let mut drag_updates = DragUpdates::new();
+        // when the collected drag updates are applied
+        let mut apply_drag_updates_at: Option<Instant> = None;
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // anything arriving counts, including the answers to the heartbeat pings
+        let mut last_heard_at = Instant::now();
+        // refreshed again once the frame is handled, answers queued up meanwhile weren't late
+        let mut is_heard = false;
+        // the revision of the players the client was last sent, changes still in the channel
+        // from before it are in there already
+        let mut synced_revision: Option<u64> = None;
         loop {
+            if socket.is_stalled {
+                logging::warn!("socket: {socket_id} stopped taking messages, closing it");
+                break;
+            }
+            if std::mem::take(&mut is_heard) {
+                last_heard_at = Instant::now();
+            }
+            // handling a message can end in many places, so its ack goes out here
+            if let Some((seq, player_names)) = pending_ack.take() {
+                let players = state.players.read().await;
+                let events = player_names
+                    .iter()
+                    .flat_map(|player_name| current_state(&players, player_name))
+                    .collect();
+                drop(players);
+                socket.send_event(&Event::Ack { seq, events }).await;
+            }
             tokio::select! {
-                Ok((sender_id, event)) = broadcast_receiver.recv() => {
-                    if sender_id == socket_id {
-                        continue;
+                kicked_by = &mut kicked => {
+                    let kicked_by = kicked_by.unwrap_or_else(|_| "the server".to_string());
+                    logging::log!("socket: {socket_id} kicked by {kicked_by}");
+                    socket.send(Message::Close(Some(CloseFrame {
+                        code: KICKED_CLOSE_CODE,
+                        reason: format!("disconnected by {kicked_by}").into(),
+                    }))).await;
+                    break;
+                }
+                _ = heartbeat.tick() => {
+                    if last_heard_at.elapsed() > HEARTBEAT_TIMEOUT {
+                        logging::warn!("socket: {socket_id} didn't answer for {:?}, closing it", last_heard_at.elapsed());
+                        break;
                     }
-                    let event = bincode::serialize(&event).unwrap();
-                    let _ = socket.send(Message::Binary(event.into())).await;
+                    socket.send(Message::Ping(Default::default())).await;
                 }
-                Some(message) = socket.recv() => {
+                _ = tokio::time::sleep_until(apply_drag_updates_at.unwrap_or_else(Instant::now)), if apply_drag_updates_at.is_some() => {
+                    apply_drag_updates_at = None;
+                    apply_drag_updates(&state, socket_id, &mut drag_updates).await;
+                }
+                event = broadcast_receiver.recv() => match event {
+                    Ok(Broadcast { sender_id, event, revision }) => {
+                        // applying a swap twice would mess up the order
+                        if let (Some(revision), Some(synced_revision)) = (revision, synced_revision)
+                            && revision <= synced_revision
+                        {
+                            continue;
+                        }
+                        // overlays don't need to know who's editing what
+                        if is_for_moderators(&event)
+                            && current_role(&state, identity.as_ref()).await.is_none_or(|role| role < Role::Moderator)
+                        {
+                            continue;
+                        }
+                        // only drag updates and presence skip their sender, it's ahead of them already
+                        if sender_id != socket_id {
+                            socket.send_event(&for_socket(event, socket_id)).await;
+                        }
+                        // the sender's revision moves on even if it skipped the change
+                        if let Some(revision) = revision {
+                            socket.send_event(&Event::Revision(revision)).await;
+                        }
+                    }
+                    // the missed events are gone, only sending everything again catches the client up
+                    Err(RecvError::Lagged(missed)) => {
+                        logging::warn!("socket: {socket_id} fell behind by {missed} events, sending all players again");
+                        // everything still in the channel is older than what gets sent now
+                        broadcast_receiver = broadcast_receiver.resubscribe();
+                        synced_revision = Some(send_all_players(&mut socket, &state).await);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                Some(stored_media) = stored_media_receiver.recv() => match stored_media {
+                    StoredMedia::DataUrl { name, media_type, position, width, height, stored } => {
+                        let asset = match stored {
+                            Ok(asset) => asset,
+                            Err(e) => {
+                                logging::error!("couldn't store {name}: {e}");
+                                reject_media(&mut socket, name, e).await;
+                                continue;
+                            }
+                        };
+                        let player = add_new_player(
+                            state.broadcaster.clone(),
+                            asset.src, media_type, position, width,
+                            height,
+                            state.players.clone(),
+                            name,
+                        ).await.unwrap();
+                        // recorded with the name it got and the library link instead of the data url
+                        let added = OverlayMessage::NewMedia {
+                            name: player.name,
+                            data: player.data,
+                            media_type: player.media_type,
+                            position: player.position,
+                            width: player.width,
+                            height: player.height,
+                        };
+                        record_change(&state, socket_id, identity.as_ref(), &added).await;
+                    }
+                    StoredMedia::Link { url, position, width, height, stored } => {
+                        let asset = match stored {
+                            Ok(asset) => asset,
+                            Err(e) => {
+                                logging::error!("couldn't fetch {url}: {e}");
+                                reject_media(&mut socket, url, e).await;
+                                continue;
+                            }
+                        };
+                        add_new_player(
+                            state.broadcaster.clone(),
+                            asset.src, asset.media_type, position, width,
+                            height,
+                            state.players.clone(),
+                            asset.name,
+                        ).await.unwrap();
+                        let added = OverlayMessage::NewMediaFromUrl { url, position, width, height };
+                        record_change(&state, socket_id, identity.as_ref(), &added).await;
+                    }
+                    StoredMedia::Upload { upload_id, upload, stored } => {
+                        storing_uploads -= 1;
+                        let asset = match stored {
+                            Ok(asset) => asset,
+                            Err(e) => {
+                                logging::error!("couldn't store {}: {e}", upload.name);
+                                reject_upload(&mut socket, upload_id, e.to_string()).await;
+                                continue;
+                            }
+                        };
+                        send_upload_progress(&mut socket, upload_id, upload.size).await;
+                        add_new_player(
+                            state.broadcaster.clone(),
+                            asset.src, asset.media_type, upload.position, upload.width,
+                            upload.height,
+                            state.players.clone(),
+                            upload.name.clone(),
+                        ).await.unwrap();
+                        let added = OverlayMessage::StartUpload {
+                            upload_id,
+                            name: upload.name,
+                            content_type: upload.content_type,
+                            size: upload.size,
+                            position: upload.position,
+                            width: upload.width,
+                            height: upload.height,
+                        };
+                        record_change(&state, socket_id, identity.as_ref(), &added).await;
+                    }
+                },
+                message = socket.socket.recv() => {
+                    let message = match message {
+                        Some(Ok(Message::Close(close_frame))) => {
+                            logging::log!("Closing websocket: {close_frame:?}");
+                            break;
+                        }
+                        Some(Ok(message)) => message,
+                        // the socket is gone without a close frame
+                        Some(Err(_)) | None => break,
+                    };
+                    last_heard_at = Instant::now();
+                    is_heard = true;
+                    // pings are answered by axum, pongs only count as being heard from
+                    let Some(message) = socket.decode(&message) else {
+                        continue;
+                    };
+                    // rejected messages are acked too, so the client drops what it applied already
+                    let message = match message {
+                        Ok(OverlayMessage::Sequenced { seq, message }) => {
+                            let player_names = message.edited_players().into_iter().map(str::to_string).collect();
+                            pending_ack = Some((seq, player_names));
+                            Ok(*message)
+                        }
+                        message => message,
+                    };
+                    if let Ok(message) = &message
+                        && let Some(required) = message.required_role()
+                    {
+                        let role = current_role(&state, identity.as_ref()).await;
+                        if role.is_none_or(|role| role < required) {
+                            logging::log!("socket: {socket_id} needs the {required:?} role, it has {role:?}");
+                            continue;
+                        }
+                    }
+                    let message = match message {
+                        Ok(message) => {
+                            let (message, denied) = without_leased_edits(&state, socket_id, message).await;
+                            // the ack corrects sequenced messages already
+                            if !denied.is_empty() && pending_ack.is_none() {
+                                // undo what the client already changed on its side
+                                let players = state.players.read().await;
+                                let corrections = denied
+                                    .iter()
+                                    .flat_map(|player_name| current_state(&players, player_name))
+                                    .collect::<Vec<_>>();
+                                drop(players);
+                                socket.send_event(&Event::Batch(corrections)).await;
+                            }
+                            let Some(message) = message else {
+                                continue;
+                            };
+                            // anything else might depend on the drag being applied, like its final values
+                            if !matches!(message, OverlayMessage::DragUpdate(_)) {
+                                apply_drag_updates_at = None;
+                                apply_drag_updates(&state, socket_id, &mut drag_updates).await;
+                            }
+                            Ok(message)
+                        }
+                        Err(e) => Err(e),
+                    };
                     match message {
-                        Ok(Message::Binary(bytes)) => {
-                            match bincode::deserialize::<OverlayMessage>(&bytes) {
-                                Ok(message) => match message {
-                                    OverlayMessage::Authorize(access_token) => {
-                                        #[cfg(debug_assertions)]
-                                        logging::log!("received access token {access_token}");
-                                        if is_authorized(access_token).await.is_ok_and(|a| a) {
-                                            #[cfg(debug_assertions)]
-                                            logging::log!("is authorized");
-                                            authorized = true;
-                                        } else {
-                                            logging::log!("not authorized");
+                        Ok(message) => match message {
+                            OverlayMessage::Authorize(access_token) => {
+                                #[cfg(debug_assertions)]
+                                logging::log!("received access token {access_token}");
+                                match Identity::authorize(&state, access_token).await {
+                                    Ok(new_identity) => {
+                                        let role = new_identity.role(&state).await;
+                                        if let Some(role) = role {
+                                            logging::log!("socket: {socket_id} authorized as {role:?}");
+                                            socket.send_event(&Event::Authorized { role }).await;
                                         }
-                                    },
-                                    OverlayMessage::SetPosition {
-                                        player_name,
-                                        new_position,
-                                    } => {
-                                        if !authorized {
-                                            logging::log!("SetPosition: not authorized");
-                                            continue;
+                                        let name = new_identity.name(&state).await;
+                                        let kind = new_identity.connection_kind(&state).await;
+                                        state.connections.write().await.authorize(socket_id, new_identity.clone(), kind);
+                                        if role.is_some_and(|role| role >= Role::Moderator) {
+                                            join_presence(&mut socket, &state, socket_id, name).await;
                                         }
+                                        identity = Some(new_identity);
+                                    }
+                                    Err(e) => logging::log!("socket: {socket_id} not authorized: {e}"),
+                                }
+                            },
+                            OverlayMessage::GetRoles => {
+                                socket.send_event(&Event::Roles(state.roles.read().await.roles())).await;
+                            }
+                            OverlayMessage::SetRole { login, role } => {
+                                let mut roles = state.roles.write().await;
+                                let is_set = roles.set(login.clone(), role).await.inspect_err(|e| logging::error!("couldn't set a role: {e}")).is_ok();
+                                let all_roles = roles.roles();
+                                drop(roles);
+                                if is_set {
+                                    record_change(&state, socket_id, identity.as_ref(), &OverlayMessage::SetRole { login, role }).await;
+                                }
+                                socket.send_event(&Event::Roles(all_roles)).await;
+                            }
+                            OverlayMessage::UpdatePresence { selection, cursor } => {
+                                let mut presence = state.presence.write().await;
+                                let Some(own_presence) = presence.get_mut(&socket_id) else {
+                                    continue;
+                                };
+                                own_presence.selection = selection;
+                                own_presence.cursor = cursor;
+                                let _ = state.broadcaster.send((socket_id, Event::PresenceUpdated(own_presence.clone())));
+                            }
+                            OverlayMessage::AcquireLeases { player_names } => {
+                                let Some(identity) = &identity else {
+                                    continue;
+                                };
+                                let holder = identity.name(&state).await;
+                                let granted = state.leases.write().await.acquire(socket_id, &holder, player_names);
+                                if granted.is_empty() {
+                                    continue;
+                                }
+                                broadcast_leases(&state, socket_id).await;
+                            }
+                            OverlayMessage::ReleaseLeases => {
+                                if state.leases.write().await.release(socket_id) {
+                                    broadcast_leases(&state, socket_id).await;
+                                }
+                            }
+                            OverlayMessage::Sequenced { .. } => {
+                                logging::log!("socket: {socket_id} sent a sequenced message inside another one, ignoring it");
+                            }
+                            OverlayMessage::GetConnections => {
+                                let connections = state.connections.read().await.connections(&state).await;
+                                socket.send_event(&Event::Connections(connections)).await;
+                            }
+                            OverlayMessage::KickConnection { socket_id: kicked_id } => {
+                                let Some(identity) = &identity else {
+                                    continue;
+                                };
+                                let kicked_by = identity.name(&state).await;
+                                let role = identity.role(&state).await;
+                                let mut connections = state.connections.write().await;
+                                // moderators can't kick editors or the owner
+                                let is_kicked = if connections.role_of(&state, kicked_id).await > role {
+                                    logging::log!("socket: {socket_id} tried to kick socket {kicked_id}, which has a higher role");
+                                    false
+                                } else if !connections.kick(kicked_id, &kicked_by) {
+                                    logging::log!("socket: {socket_id} tried to kick socket {kicked_id}, which isn't connected");
+                                    false
+                                } else {
+                                    true
+                                };
+                                let remaining = connections.connections(&state).await;
+                                drop(connections);
+                                if is_kicked {
+                                    record_change(&state, socket_id, Some(identity), &OverlayMessage::KickConnection { socket_id: kicked_id }).await;
+                                }
+                                socket.send_event(&Event::Connections(remaining)).await;
+                            }
+                            OverlayMessage::GetAuditLog { actor, action } => {
+                                let filter = Filter::new(actor.as_deref(), action.as_deref());
+                                let entries = audit::search(&state.audit_log, &filter).await;
+                                socket.send_event(&Event::AuditLog(entries)).await;
+                            }
+                            OverlayMessage::GetApiTokens => {
+                                socket.send_event(&Event::ApiTokens(state.tokens.read().await.tokens())).await;
+                            }
+                            OverlayMessage::CreateApiToken { name, scope: token_scope } => {
+                                let mut tokens = state.tokens.write().await;
+                                match tokens.create(name.clone(), token_scope).await {
+                                    Ok((token, secret)) => {
+                                        let all_tokens = tokens.tokens();
+                                        drop(tokens);
+                                        record_change(&state, socket_id, identity.as_ref(), &OverlayMessage::CreateApiToken { name, scope: token_scope }).await;
+                                        socket.send_event(&Event::ApiTokenCreated { token, secret }).await;
+                                        socket.send_event(&Event::ApiTokens(all_tokens)).await;
+                                    }
+                                    Err(e) => logging::error!("couldn't create api token: {e}"),
+                                }
+                            }
+                            OverlayMessage::RevokeApiToken { token_id } => {
+                                let mut tokens = state.tokens.write().await;
+                                let is_revoked = tokens.revoke(&token_id).await.inspect_err(|e| logging::error!("couldn't revoke api token {token_id}: {e}")).is_ok();
+                                let all_tokens = tokens.tokens();
+                                drop(tokens);
+                                // the token is gone by now, so a socket using it is recorded as a revoked token
+                                if is_revoked {
+                                    record_change(&state, socket_id, identity.as_ref(), &OverlayMessage::RevokeApiToken { token_id }).await;
+                                }
+                                socket.send_event(&Event::ApiTokens(all_tokens)).await;
+                            }
+                            OverlayMessage::NewMedia { name, data, media_type, position, width, height } => {
+                                // base64 makes the data url about a third bigger than the file
+                                if media_type != MediaType::Text && data.len() / 4 * 3 > state.max_upload_size {
+                                    reject_media(&mut socket, name, MediaError::TooLarge { limit: state.max_upload_size }).await;
+                                    continue;
+                                }
+                                // uploaded files go into the media library, the player only links to them
+                                if data.starts_with("data:") && media_type != MediaType::Text {
+                                    let state = state.clone();
+                                    let stored_media_sender = stored_media_sender.clone();
+                                    tokio::spawn(async move {
+                                        let stored = store_data_url(&state.media_library, name.clone(), &data).await;
+                                        let stored = announce_stored(&state, stored);
+                                        let _ = stored_media_sender.send(StoredMedia::DataUrl { name, media_type, position, width, height, stored });
+                                    });
+                                    continue;
+                                }
+                                let player = add_new_player(
+                                    state.broadcaster.clone(),
+                                    data, media_type, position, width,
+                                    height,
+                                    state.players.clone(),
+                                    name,
+                                ).await.unwrap();
+                                // recorded with the name it got and the library link instead of the data url
+                                let added = OverlayMessage::NewMedia {
+                                    name: player.name,
+                                    data: player.data,
+                                    media_type: player.media_type,
+                                    position: player.position,
+                                    width: player.width,
+                                    height: player.height,
+                                };
+                                record_change(&state, socket_id, identity.as_ref(), &added).await;
+                            },
+                            OverlayMessage::NewMediaFromUrl { url, position, width, height } => {
+                                let state = state.clone();
+                                let stored_media_sender = stored_media_sender.clone();
+                                tokio::spawn(async move {
+                                    let stored = fetch_remote_media(&state.media_library, &url, state.max_upload_size).await;
+                                    let stored = announce_stored(&state, stored);
+                                    let _ = stored_media_sender.send(StoredMedia::Link { url, position, width, height, stored });
+                                });
+                            }
+                            OverlayMessage::StartUpload { upload_id, name, content_type, size, position, width, height } => {
+                                let rejection = if size == 0 {
+                                    Some(MediaError::Empty)
+                                } else if size > state.max_upload_size as u64 {
+                                    Some(MediaError::TooLarge { limit: state.max_upload_size })
+                                } else if !is_supported(&content_type) {
+                                    Some(MediaError::UnsupportedType(content_type.clone()))
+                                } else {
+                                    None
+                                };
+                                if let Some(e) = rejection {
+                                    reject_upload(&mut socket, upload_id, e.to_string()).await;
+                                    continue;
+                                }
+                                if uploads.len() + storing_uploads >= MAX_UPLOADS_PER_SOCKET && !uploads.contains_key(&upload_id) {
+                                    reject_upload(&mut socket, upload_id, format!("at most {MAX_UPLOADS_PER_SOCKET} uploads at once")).await;
+                                    continue;
+                                }
+                                // the size is only announced, the buffer grows with what actually arrives
+                                uploads.insert(upload_id, PendingUpload {
+                                    name, content_type, size, position, width, height,
+                                    bytes: Vec::new(),
+                                });
+                                send_upload_progress(&mut socket, upload_id, 0).await;
+                            }
+                            OverlayMessage::UploadChunk { upload_id, data } => {
+                                let Some(upload) = uploads.get_mut(&upload_id) else {
+                                    continue;
+                                };
+                                // the declared size can't be trusted, so check what actually arrives
+                                if data.len() > UPLOAD_CHUNK_SIZE || upload.bytes.len() + data.len() > upload.size as usize {
+                                    uploads.remove(&upload_id);
+                                    reject_upload(&mut socket, upload_id, "upload is bigger than announced".to_string()).await;
+                                    continue;
+                                }
+                                upload.bytes.extend_from_slice(&data);
+                                let received = upload.bytes.len() as u64;
+                                if received < upload.size {
+                                    send_upload_progress(&mut socket, upload_id, received).await;
+                                    continue;
+                                }
 
-                                        let mut lock = state.players.write().await;
-                                        let Some(player) = lock.get_mut(&player_name) else {
-                                            continue;
-                                        };
-                                        player.position = new_position;
-
-                                        let event = Event::PositionUpdated {
-                                            player_name: player.name.clone(),
-                                            new_position: player.position.clone(),
-                                        };
+                                let mut upload = uploads.remove(&upload_id).expect("upload should exist");
+                                storing_uploads += 1;
+                                let state = state.clone();
+                                let stored_media_sender = stored_media_sender.clone();
+                                tokio::spawn(async move {
+                                    let bytes = std::mem::take(&mut upload.bytes);
+                                    let stored = store_upload(&state.media_library, upload.name.clone(), &upload.content_type, &bytes).await;
+                                    let stored = announce_stored(&state, stored);
+                                    let _ = stored_media_sender.send(StoredMedia::Upload { upload_id, upload, stored });
+                                });
+                            }
+                            OverlayMessage::CancelUpload { upload_id } => {
+                                uploads.remove(&upload_id);
+                            }
+                            OverlayMessage::GetMediaLibrary => {
+                                let assets = state.media_library.read().await.assets();
+                                socket.send_event(&Event::MediaLibrary(assets)).await;
+                            }
+                            OverlayMessage::NewMediaFromLibrary { asset_id, position, width, height } => {
+                                let Some(asset) = state.media_library.read().await.get(&asset_id).cloned() else {
+                                    continue;
+                                };
+                                add_new_player(
+                                    state.broadcaster.clone(),
+                                    asset.src, asset.media_type, position, width,
+                                    height,
+                                    state.players.clone(),
+                                    asset.name,
+                                ).await.unwrap();
+                                let added = OverlayMessage::NewMediaFromLibrary { asset_id, position, width, height };
+                                record_change(&state, socket_id, identity.as_ref(), &added).await;
+                            }
+                            OverlayMessage::PurgeMedia { asset_id } => {
+                                let Some(asset) = state.media_library.read().await.get(&asset_id).cloned() else {
+                                    continue;
+                                };
 
-                                        // notify other clients
-                                        let _ = state.broadcaster.send((socket_id, event.clone()));
+                                // the players showing it would only show a broken image now
+                                let mut players = state.players.write().await;
+                                let showing = players
+                                    .values()
+                                    .filter(|player| player.data == asset.src)
+                                    .map(|player| player.name.clone())
+                                    .collect::<Vec<_>>();
+                                // deleting them would pull them away from whoever is dragging them
+                                let leased = {
+                                    let mut leases = state.leases.write().await;
+                                    showing.iter().find_map(|player_name| {
+                                        leases.check(socket_id, player_name).err().map(|lease| (player_name.clone(), lease.holder))
+                                    })
+                                };
+                                if let Some((player_name, holder)) = leased {
+                                    drop(players);
+                                    reject_media(&mut socket, asset.name, MediaError::Leased { player_name, holder }).await;
+                                    continue;
+                                }
 
-                                        // NOTE: this might be better to keep commented out to make the client experience a bit better
-                                        // let event = bincode::serialize(&event).unwrap();
-                                        // let _ = socket.send(Message::Binary(event)).await;
+                                match state.media_library.write().await.purge(&asset_id).await {
+                                    Ok(Some(_)) => {}
+                                    Ok(None) => continue,
+                                    Err(e) => {
+                                        logging::error!("couldn't purge {asset_id}: {e}");
+                                        continue;
                                     }
-                                    OverlayMessage::NewMedia { name, data, media_type, position, width, height } => {
-                                        if !authorized {
-                                            logging::log!("NewPlayer not authorized");
-                                            continue;
-                                        }
-                                        add_new_player(socket_id,
-                                            state.broadcaster.clone(),
-                                            data, media_type, position, width,
-                                            height, &mut socket,
-                                            state.players.clone(),
-                                            name,
-                                        ).await.unwrap()
-                                    },
-                                    OverlayMessage::GetAllPlayers => {
-                                        logging::log!("Received request for all players");
-                                        let event = bincode::serialize(&Event::AllPlayers(
-                                            state.players.read().await.clone(),
-                                        ))
-                                        .unwrap();
-                                        let _ = socket.send(Message::Binary(event.into())).await;
+                                }
+                                let deleted = showing
+                                    .into_iter()
+                                    .filter_map(|player_name| apply_message(&mut players, OverlayMessage::DeletePlayer { player_name }))
+                                    .collect::<Vec<_>>();
+                                if !deleted.is_empty() {
+                                    let _ = state.broadcaster.send((NO_SENDER_ID, Event::Batch(deleted)));
+                                }
+                                drop(players);
+                                record_change(&state, socket_id, identity.as_ref(), &OverlayMessage::PurgeMedia { asset_id: asset_id.clone() }).await;
+                                let _ = state.broadcaster.send((NO_SENDER_ID, Event::MediaPurged { asset_id }));
+                            }
+                            OverlayMessage::GetAllPlayers => {
+                                logging::log!("Received request for all players");
+                                synced_revision = Some(send_all_players(&mut socket, &state).await);
+                            }
+                            OverlayMessage::GetChangesSince { revision } => {
+                                let players = state.players.read().await;
+                                let Some(changes) = state.broadcaster.changes_since(revision) else {
+                                    drop(players);
+                                    logging::log!("socket: {socket_id} is too far behind for changes, sending all players");
+                                    synced_revision = Some(send_all_players(&mut socket, &state).await);
+                                    continue;
+                                };
+                                let current = state.broadcaster.revision();
+                                drop(players);
+                                synced_revision = Some(current);
+                                if !changes.is_empty() {
+                                    socket.send_event(&Event::Batch(changes)).await;
+                                }
+                                socket.send_event(&Event::Revision(current)).await;
+                            }
+                            OverlayMessage::DragUpdate(messages) => {
+                                for message in messages {
+                                    if !matches!(message, OverlayMessage::SetPosition { .. } | OverlayMessage::SetSize { .. }) {
+                                        continue;
                                     }
-                                    OverlayMessage::SetSize { player_name, width, height } => {
-                                        if !authorized {
-                                            logging::log!("SetSize not authorized");
-                                            continue;
-                                        }
-                                        let mut players = state.players.write().await;
-                                        let Some(player) = players.get_mut(&player_name) else {
-                                            continue;
-                                        };
-
-                                        player.width = width;
-                                        player.height = height;
-
-                                        let event = Event::SizeUpdated {
-                                            player_name: player.name.clone(),
-                                            new_width: player.width,
-                                            new_height: player.height,
-                                        };
-
-                                        let _ = state.broadcaster.send((socket_id, event.clone()));
-
-                                        // let event = bincode::serialize(&event).unwrap();
-
-                                        // let _ = socket.send(Message::Binary(event)).await;
-                                    },
-                                    OverlayMessage::DeletePlayer { player_name } => {
-                                        if !authorized {
-                                            logging::log!("DeletePlayer not authorized");
-                                            continue;
-                                        }
-                                        let mut players = state.players.write().await;
-                                        if players.shift_remove(&player_name).is_some() {
-                                            let event = Event::PlayerDeleted { player_name };
+                                    let Some(player_name) = message.edited_player() else {
+                                        continue;
+                                    };
+                                    let key = (player_name.to_string(), std::mem::discriminant(&message));
+                                    drag_updates.insert(key, message);
+                                }
+                                if !drag_updates.is_empty() {
+                                    apply_drag_updates_at.get_or_insert_with(|| Instant::now() + DRAG_UPDATE_INTERVAL);
+                                }
+                            }
+                            OverlayMessage::Batch(messages) => {
+                                let mut players = state.players.write().await;
+                                let (applied, events): (Vec<_>, Vec<_>) = messages
+                                    .into_iter()
+                                    .filter_map(|message| Some((message.clone(), apply_message(&mut players, message)?)))
+                                    .unzip();
 
-                                            let _ = state.broadcaster.send((socket_id, event.clone()));
+                                if events.is_empty() {
+                                    continue;
+                                }
 
-                                            let event = bincode::serialize(&event).unwrap();
-                                            let _ = socket.send(Message::Binary(event.into())).await;
-                                        }
-                                    },
-                                    OverlayMessage::MovePlayerUp { player_name } => {
-                                        if !authorized {
-                                            logging::log!("MovePlayerUp not authorized");
-                                            continue;
-                                        }
-                                        let mut players = state.players.write().await;
-                                        if let Some(s) = players.get_index_of(&player_name) {
-                                            if s > 0 {
-                                                logging::log!("moving {player_name} up");
-                                                players.swap_indices(s, s - 1);
-                                                let event = Event::PlayerMovedUp { player_name };
-
-                                                let _ = state.broadcaster.send((socket_id, event.clone()));
-
-                                                let event = bincode::serialize(&event).unwrap();
-                                                let _ = socket.send(Message::Binary(event.into())).await;
-                                            }
-                                        }
-                                    },
-                                    OverlayMessage::MovePlayerDown { player_name } => {
-                                        if !authorized {
-                                            logging::log!("MovePlayerDown not authorized");
-                                            continue;
-                                        }
-                                        let mut players = state.players.write().await;
-                                        if let Some(s) = players.get_index_of(&player_name) {
-                                            if players.len() > s + 1 {
-                                                logging::log!("moving {player_name} down");
-                                                players.swap_indices(s, s + 1);
+                                // the sender gets it too, its pending edits keep it from undoing newer ones
+                                let _ = state.broadcaster.send((NO_SENDER_ID, Event::Batch(events)));
+                                drop(players);
+                                record_change(&state, socket_id, identity.as_ref(), &OverlayMessage::Batch(applied)).await;
+                            }
+                            message @ (OverlayMessage::SetPosition { .. }
+                            | OverlayMessage::SetSize { .. }
+                            | OverlayMessage::DeletePlayer { .. }
+                            | OverlayMessage::MovePlayerUp { .. }
+                            | OverlayMessage::MovePlayerDown { .. }
+                            | OverlayMessage::FlipPlayerHorizontally { .. }
+                            | OverlayMessage::DuplicatePlayer { .. }) => {
+                                let mut players = state.players.write().await;
+                                let Some(event) = apply_message(&mut players, message.clone()) else {
+                                    continue;
+                                };
 
-                                                let event = Event::PlayerMovedDown { player_name };
+                                let _ = state.broadcaster.send((NO_SENDER_ID, event));
+                                drop(players);
+                                record_change(&state, socket_id, identity.as_ref(), &message).await;
+                            }
+                            OverlayMessage::Ping => {
+                                #[cfg(debug_assertions)]
+                                logging::log!("socket: {socket_id} ping");
+                                socket.send_event(&Event::Pong).await;
+                            }
+                        },
+                        // most likely a newer client, which is fine as long as the version matches
+                        Err(e) => logging::warn!("socket: {socket_id} sent a message that couldn't be decoded: {e}"),
+                    }
+                }
+            }
+        }
 
-                                                let _ = state.broadcaster.send((socket_id, event.clone()));
+        // keep where a drag ended even if its final values never arrived
+        apply_drag_updates(&state, socket_id, &mut drag_updates).await;
+        state.connections.write().await.unregister(socket_id);
+        leave_presence(&state, socket_id).await;
+        if state.leases.write().await.release(socket_id) {
+            broadcast_leases(&state, socket_id).await;
+        }
+    }
 
-                                                let event = bincode::serialize(&event).unwrap();
-                                                let _ = socket.send(Message::Binary(event.into())).await;
-                                            }
-                                        }
-                                    },
-                                    OverlayMessage::FlipPlayerHorizontally { player_name, is_flipped } => {
-                                        if !authorized {
-                                            logging::log!("FlipPlayerHorizontally not authorized");
-                                            continue;
-                                        }
-                                        let mut players = state.players.write().await;
-                                        let Some(player) = players.get_mut(&player_name) else {
-                                            continue;
-                                        };
+    /// Drops edits of players someone else holds a lease on, returning what's left and the denied players
+    async fn without_leased_edits(
+        state: &AppState,
+        socket_id: u32,
+        message: OverlayMessage,
+    ) -> (Option<OverlayMessage>, Vec<String>) {
+        let mut leases = state.leases.write().await;
+        let mut denied = Vec::new();
+        let mut is_allowed = |message: &OverlayMessage| {
+            let Some(player_name) = message.edited_player() else {
+                return true;
+            };
+            match leases.check(socket_id, player_name) {
+                Ok(()) => true,
+                Err(lease) => {
+                    logging::log!(
+                        "socket: {socket_id} can't edit {player_name}, {} is editing it",
+                        lease.holder
+                    );
+                    if !denied.iter().any(|denied| denied == player_name) {
+                        denied.push(player_name.to_string());
+                    }
+                    false
+                }
+            }
+        };
 
-                                        player.horizontal_flip = is_flipped;
+        let message = match message {
+            OverlayMessage::Batch(messages) => Some(OverlayMessage::Batch(
+                messages
+                    .into_iter()
+                    .filter(|message| is_allowed(message))
+                    .collect(),
+            )),
+            OverlayMessage::DragUpdate(messages) => Some(OverlayMessage::DragUpdate(
+                messages
+                    .into_iter()
+                    .filter(|message| is_allowed(message))
+                    .collect(),
+            )),
+            message => is_allowed(&message).then_some(message),
+        };
 
-                                        let event = Event::FlipPlayerHorizontally { player_name, is_flipped };
+        (message, denied)
+    }
 
-                                        let _ = state.broadcaster.send((socket_id, event.clone()));
-                                    }
-                                    OverlayMessage::Ping => {
-                                        #[cfg(debug_assertions)]
-                                        logging::log!("socket: {socket_id} ping");
-                                        let event = bincode::serialize(&Event::Pong).unwrap();
-                                        let _ = socket.send(Message::Binary(event.into())).await;
-                                    }
-                                },
-                                Err(e) => logging::error!("{e}"),
-                            }
-                        },
-                        Ok(Message::Close(close_frame)) => {
-                            logging::log!("Closing websocket: {close_frame:?}");
-                            break;
-                        }
-                        _ => break,
-                    }
+    /// Sends every player along with their revision, which later changes are counted from,
+    /// returns the revision
+    async fn send_all_players(socket: &mut Connection, state: &AppState) -> u64 {
+        let players = state.players.read().await;
+        let all_players = players.clone();
+        let revision = state.broadcaster.revision();
+        drop(players);
+
+        socket.send_event(&Event::AllPlayers(all_players)).await;
+        socket.send_event(&Event::Revision(revision)).await;
+
+        revision
+    }
+
+    /// Applies the collected drag updates under one lock and with one broadcast
+    async fn apply_drag_updates(state: &AppState, socket_id: u32, drag_updates: &mut DragUpdates) {
+        if drag_updates.is_empty() {
+            return;
+        }
+
+        let mut players = state.players.write().await;
+        let events = drag_updates
+            .drain(..)
+            .filter_map(|(_, message)| apply_message(&mut players, message))
+            .collect::<Vec<_>>();
+        if !events.is_empty() {
+            let _ = state.broadcaster.send((socket_id, Event::Batch(events)));
+        }
+    }
+
+    /// Events resetting a client's copy of the player to what the server has
+    fn current_state(players: &IndexMap<String, ServerPlayer>, player_name: &str) -> Vec<Event> {
+        let Some(player) = players.get(player_name) else {
+            return Vec::new();
+        };
+
+        vec![
+            Event::PositionUpdated {
+                player_name: player_name.to_string(),
+                new_position: player.position,
+            },
+            Event::SizeUpdated {
+                player_name: player_name.to_string(),
+                new_width: player.width,
+                new_height: player.height,
+            },
+            Event::FlipPlayerHorizontally {
+                player_name: player_name.to_string(),
+                is_flipped: player.horizontal_flip,
+            },
+        ]
+    }
+
+    async fn broadcast_leases(state: &AppState, sender_id: u32) {
+        let leases = state.leases.read().await.leases();
+        let _ = state.broadcaster.send((sender_id, Event::Leases(leases)));
+    }
+
+    /// Removes leases that ran out and tells everyone, runs as long as the server does
+    pub async fn expire_leases(state: AppState) {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            let expired = state.leases.write().await.expire();
+            if expired {
+                logging::log!("leases ran out");
+                broadcast_leases(&state, NO_SENDER_ID).await;
+            }
+        }
+    }
+
+    /// Shows the socket to the other editors and tells it who else is there,
+    /// authorizing again only changes the name
+    async fn join_presence(
+        socket: &mut Connection,
+        state: &AppState,
+        socket_id: u32,
+        name: String,
+    ) {
+        let mut presence = state.presence.write().await;
+        let color = color_of(&name);
+        presence
+            .entry(socket_id)
+            .and_modify(|own_presence| {
+                own_presence.name = name.clone();
+                own_presence.color = color.clone();
+            })
+            .or_insert_with(|| Presence {
+                socket_id,
+                name,
+                color,
+                selection: Vec::new(),
+                cursor: None,
+            });
+        let everyone = presence.values().cloned().collect::<Vec<_>>();
+        drop(presence);
+
+        let _ = state
+            .broadcaster
+            .send((socket_id, Event::Presence(everyone.clone())));
+        socket
+            .send_event(&for_socket(Event::Presence(everyone), socket_id))
+            .await;
+        // someone joining mid-drag should see what's held already
+        let leases = state.leases.read().await.leases();
+        socket
+            .send_event(&for_socket(Event::Leases(leases), socket_id))
+            .await;
+    }
+
+    async fn leave_presence(state: &AppState, socket_id: u32) {
+        let mut presence = state.presence.write().await;
+        if presence.shift_remove(&socket_id).is_none() {
+            return;
+        }
+        let everyone = presence.values().cloned().collect::<Vec<_>>();
+        drop(presence);
+
+        let _ = state
+            .broadcaster
+            .send((socket_id, Event::Presence(everyone)));
+    }
+
+    /// Records a change in the audit log once it was applied, under the name of whoever made it
+    async fn record_change(
+        state: &AppState,
+        socket_id: u32,
+        identity: Option<&Identity>,
+        message: &OverlayMessage,
+    ) {
+        let Some(identity) = identity else {
+            return;
+        };
+        let actor = identity.name(state).await;
+        state
+            .audit_log
+            .write()
+            .await
+            .record(Some(socket_id), &actor, message)
+            .await;
+    }
+
+    /// The socket's role right now, `None` until it's authorized
+    async fn current_role(state: &AppState, identity: Option<&Identity>) -> Option<Role> {
+        match identity {
+            Some(identity) => identity.role(state).await,
+            None => None,
+        }
+    }
+
+    /// Events showing who's editing what, only the control page gets them
+    fn is_for_moderators(event: &Event) -> bool {
+        matches!(
+            event,
+            Event::Presence(_) | Event::PresenceUpdated(_) | Event::Leases(_)
+        )
+    }
+
+    /// Presence and lease lists are the same for everyone, but each socket only gets the others'
+    fn for_socket(event: Event, socket_id: u32) -> Event {
+        match event {
+            Event::Presence(everyone) => Event::Presence(
+                everyone
+                    .into_iter()
+                    .filter(|presence| presence.socket_id != socket_id)
+                    .collect(),
+            ),
+            Event::Leases(leases) => Event::Leases(
+                leases
+                    .into_iter()
+                    .filter(|(_, lease)| lease.socket_id != socket_id)
+                    .collect(),
+            ),
+            event => event,
+        }
+    }
+
+    /// A hue picked from the name, so everyone keeps their color between reconnects
+    fn color_of(name: &str) -> String {
+        let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+
+        format!("hsl({}, 80%, 60%)", hash % 360)
+    }
+
+    /// Applies a single player mutation, returning the event describing it
+    ///
+    /// Returns `None` if the message isn't a mutation or didn't change anything
+    pub(crate) fn apply_message(
+        players: &mut IndexMap<String, ServerPlayer>,
+        message: OverlayMessage,
+    ) -> Option<Event> {
+        match message {
+            OverlayMessage::SetPosition {
+                player_name,
+                new_position,
+            } => {
+                let player = players.get_mut(&player_name)?;
+                player.position = new_position;
+
+                Some(Event::PositionUpdated {
+                    player_name,
+                    new_position,
+                })
+            }
+            OverlayMessage::SetSize {
+                player_name,
+                width,
+                height,
+            } => {
+                let player = players.get_mut(&player_name)?;
+                player.width = width.max(MIN_PLAYER_SIZE);
+                player.height = height.map(|height| height.max(MIN_PLAYER_SIZE));
+
+                Some(Event::SizeUpdated {
+                    player_name,
+                    new_width: player.width,
+                    new_height: player.height,
+                })
+            }
+            OverlayMessage::DeletePlayer { player_name } => players
+                .shift_remove(&player_name)
+                .map(|_| Event::PlayerDeleted { player_name }),
+            OverlayMessage::MovePlayerUp { player_name } => {
+                let s = players.get_index_of(&player_name)?;
+                if s == 0 {
+                    return None;
                 }
+                logging::log!("moving {player_name} up");
+                players.swap_indices(s, s - 1);
+
+                Some(Event::PlayerMovedUp { player_name })
+            }
+            OverlayMessage::MovePlayerDown { player_name } => {
+                let s = players.get_index_of(&player_name)?;
+                if players.len() <= s + 1 {
+                    return None;
+                }
+                logging::log!("moving {player_name} down");
+                players.swap_indices(s, s + 1);
+
+                Some(Event::PlayerMovedDown { player_name })
+            }
+            OverlayMessage::FlipPlayerHorizontally {
+                player_name,
+                is_flipped,
+            } => {
+                let player = players.get_mut(&player_name)?;
+                player.horizontal_flip = is_flipped;
+
+                Some(Event::FlipPlayerHorizontally {
+                    player_name,
+                    is_flipped,
+                })
+            }
+            OverlayMessage::DuplicatePlayer {
+                player_name,
+                offset,
+            } => {
+                let mut player = players.get(&player_name)?.clone();
+                player.name = unique_name(players, player_name);
+                player.position = player.position + offset;
+                players.insert(player.name.clone(), player.clone());
+
+                Some(Event::NewPlayer(player))
             }
+            OverlayMessage::Ping
+            | OverlayMessage::Authorize(_)
+            | OverlayMessage::GetAllPlayers
+            | OverlayMessage::GetChangesSince { .. }
+            | OverlayMessage::GetConnections
+            | OverlayMessage::KickConnection { .. }
+            | OverlayMessage::NewMedia { .. }
+            | OverlayMessage::NewMediaFromUrl { .. }
+            | OverlayMessage::StartUpload { .. }
+            | OverlayMessage::UploadChunk { .. }
+            | OverlayMessage::CancelUpload { .. }
+            | OverlayMessage::GetMediaLibrary
+            | OverlayMessage::NewMediaFromLibrary { .. }
+            | OverlayMessage::PurgeMedia { .. }
+            | OverlayMessage::Batch(_)
+            | OverlayMessage::DragUpdate(_)
+            | OverlayMessage::GetApiTokens
+            | OverlayMessage::CreateApiToken { .. }
+            | OverlayMessage::RevokeApiToken { .. }
+            | OverlayMessage::GetRoles
+            | OverlayMessage::SetRole { .. }
+            | OverlayMessage::GetAuditLog { .. }
+            | OverlayMessage::UpdatePresence { .. }
+            | OverlayMessage::AcquireLeases { .. }
+            | OverlayMessage::ReleaseLeases
+            | OverlayMessage::Sequenced { .. } => None,
+        }
+    }
+
+    /// Tells everyone about media that wasn't in the library before, even if its socket closed meanwhile
+    fn announce_stored(
+        state: &AppState,
+        stored: Result<(MediaAsset, bool), MediaError>,
+    ) -> Result<MediaAsset, MediaError> {
+        let (asset, is_new) = stored?;
+        if is_new {
+            let _ = state
+                .broadcaster
+                .send((NO_SENDER_ID, Event::MediaAdded(asset.clone())));
         }
+
+        Ok(asset)
+    }
+
+    async fn send_upload_progress(socket: &mut Connection, upload_id: u32, received: u64) {
+        socket
+            .send_event(&Event::UploadProgress {
+                upload_id,
+                received,
+            })
+            .await;
+    }
+
+    async fn reject_upload(socket: &mut Connection, upload_id: u32, reason: String) {
+        socket
+            .send_event(&Event::UploadRejected { upload_id, reason })
+            .await;
+    }
+
+    async fn reject_media(socket: &mut Connection, name: String, error: MediaError) {
+        socket
+            .send_event(&Event::MediaRejected {
+                name,
+                reason: error.to_string(),
+            })
+            .await;
+    }
+
+    /// Returns `name`, or `name` with the first free `-N` suffix if it's already taken
+    fn unique_name(players: &IndexMap<String, ServerPlayer>, name: String) -> String {
+        if !players.contains_key(&name) {
+            return name;
+        }
+
+        // duplicating "meme-2" should give "meme-3" and not "meme-2-2"
+        let base = name
+            .rsplit_once('-')
+            .filter(|(_, suffix)| suffix.parse::<u32>().is_ok())
+            .map(|(base, _)| base.to_string())
+            .unwrap_or(name);
+
+        (2..)
+            .map(|count| format!("{base}-{count}"))
+            .find(|name| !players.contains_key(name))
+            .expect("there should be a free name")
+    }
+
+    /// Adds a player on top of the others, renaming it if the name is taken
+    pub(crate) fn insert_new_player(
+        players: &mut IndexMap<String, ServerPlayer>,
+        name: String,
+        data: String,
+        media_type: MediaType,
+        position: crate::Position,
+        width: i32,
+        height: Option<i32>,
+    ) -> ServerPlayer {
+        let player = ServerPlayer {
+            name: unique_name(players, name),
+            data,
+            media_type,
+            position,
+            width: width.max(MIN_PLAYER_SIZE),
+            height: height.map(|height| height.max(MIN_PLAYER_SIZE)),
+            horizontal_flip: false,
+        };
+        logging::log!("adding new player: {:?}", player.media_type);
+
+        players.insert(player.name.clone(), player.clone());
+
+        player
     }
 
     async fn add_new_player(
-        socket_id: u32,
-        broadcaster: tokio::sync::broadcast::Sender<(u32, Event)>,
+        broadcaster: Broadcaster,
         data: String,
         media_type: MediaType,
         position: crate::Position,
         width: i32,
         height: Option<i32>,
-        socket: &mut axum::extract::ws::WebSocket,
         players: std::sync::Arc<tokio::sync::RwLock<IndexMap<String, ServerPlayer>>>,
         name: String,
-    ) -> anyhow::Result<()> {
-        let duplicated_count = players
-            .read()
-            .await
-            .iter()
-            .filter(|(p_name, _)| p_name.starts_with(&name))
-            .count();
-
-        logging::log!("duplicates: {duplicated_count}");
-
-        let name = if duplicated_count > 0 {
-            let mut stripped = name
-                .strip_suffix(&format!("-{duplicated_count}"))
-                .map(|s| s.to_string())
-                .unwrap_or(name);
-            stripped.push_str(&format!("-{}", duplicated_count + 1));
-            stripped
-        } else {
-            name
-        };
-
-        let player = ServerPlayer {
+    ) -> anyhow::Result<ServerPlayer> {
+        let mut players = players.write().await;
+        let player = insert_new_player(
+            &mut players,
             name,
             data,
             media_type,
             position,
             width,
             height,
-            horizontal_flip: false,
-        };
-        logging::log!("adding new player: {:?}", player.media_type);
+        );
+        // sent under the lock so a snapshot can't have the player without its revision
+        let _ = broadcaster.send((NO_SENDER_ID, Event::NewPlayer(player.clone())));
+        drop(players);
 
-        players
-            .write()
-            .await
-            .insert(player.name.clone(), player.clone());
+        Ok(player)
+    }
 
-        let event = Event::NewPlayer(player);
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-        let _ = broadcaster.send((socket_id, event.clone()));
+        fn players(names: &[&str]) -> IndexMap<String, ServerPlayer> {
+            let mut players = IndexMap::new();
+            for name in names {
+                insert_new_player(
+                    &mut players,
+                    name.to_string(),
+                    "text".to_string(),
+                    MediaType::Text,
+                    crate::Position::new(0, 0),
+                    100,
+                    None,
+                );
+            }
+            players
+        }
 
-        let event = bincode::serialize(&event).unwrap();
-        let _ = socket.send(Message::Binary(event.into())).await;
+        #[test]
+        fn keeps_free_names() {
+            assert_eq!(
+                unique_name(&players(&["meme-2"]), "meme".to_string()),
+                "meme"
+            );
+        }
 
-        Ok(())
-    }
-}
+        #[test]
+        fn adds_the_first_free_suffix() {
+            assert_eq!(
+                unique_name(&players(&["meme"]), "meme".to_string()),
+                "meme-2"
+            );
+            assert_eq!(
+                unique_name(&players(&["meme", "meme-2", "meme-4"]), "meme".to_string()),
+                "meme-3"
+            );
+        }
 
-#[server(IncrementCounter, "/api/counter")]
-pub async fn increment_counter() -> Result<String, ServerFnError> {
-    use crate::AppState;
+        #[test]
+        fn counts_on_from_a_suffix() {
+            let players = players(&["meme", "meme-2"]);
 
-    let state = use_context::<AppState>().unwrap();
+            assert_eq!(unique_name(&players, "meme-2".to_string()), "meme-3");
+        }
+
+        #[test]
+        fn keeps_dashes_that_arent_suffixes() {
+            let players = players(&["my-meme", "meme-2b"]);
+
+            assert_eq!(unique_name(&players, "my-meme".to_string()), "my-meme-2");
+            assert_eq!(unique_name(&players, "meme-2b".to_string()), "meme-2b-2");
+        }
 
-    Ok(format!("{state:?}"))
+        #[test]
+        fn inserted_players_get_unique_names() {
+            let players = players(&["meme", "meme", "meme"]);
+
+            assert_eq!(
+                players.keys().collect::<Vec<_>>(),
+                ["meme", "meme-2", "meme-3"]
+            );
+        }
+    }
 }
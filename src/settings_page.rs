@@ -0,0 +1,133 @@
+use codee::string::JsonSerdeCodec;
+use indexmap::IndexMap;
+use leptos::prelude::*;
+use leptos_use::{core::ConnectionReadyState, storage::use_local_storage};
+
+use crate::{Event, Message, Role, app::WebsocketContext, control_page::use_session};
+
+/// Lets the owner assign roles to twitch logins
+#[component]
+pub fn SettingsPage() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let (access_token, _, _) = use_local_storage::<Option<String>, JsonSerdeCodec>("access_token");
+    let (_, role) = use_session(access_token);
+    let (roles, set_roles) = signal(IndexMap::<String, Role>::new());
+    let (login, set_login) = signal(String::new());
+    let (new_role, set_new_role) = signal(Role::Editor);
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            if role() == Some(Role::Owner)
+                && let ConnectionReadyState::Open = websocket.ready_state.get()
+            {
+                websocket.send(&bincode::serialize(&Message::GetRoles).unwrap());
+            }
+        });
+    }
+
+    {
+        let websocket = websocket.clone();
+        Effect::new(move |_| {
+            if let Some(message) = websocket.message.get()
+                && let Ok(Event::Roles(assigned)) = bincode::deserialize::<Event>(&message)
+            {
+                set_roles(assigned);
+            }
+        });
+    }
+
+    let set_role = Callback::new(move |(login, role): (String, Option<Role>)| {
+        let message = Message::SetRole { login, role };
+        websocket.send(&bincode::serialize(&message).unwrap());
+    });
+
+    let assign = move |_| {
+        let assigned_login = login.get_untracked().trim().to_string();
+        if assigned_login.is_empty() {
+            return;
+        }
+        set_role.run((assigned_login, Some(new_role.get_untracked())));
+        set_login(String::new());
+    };
+
+    view! {
+        <Show
+            when=move || role() == Some(Role::Owner)
+            fallback=|| {
+                view! {
+                    <p>
+                        "Only the owner can change roles. "
+                        <a href="/control">"Back to the control page"</a>
+                    </p>
+                }
+            }
+        >
+            <div style="max-width: 40rem; margin: 0 auto;">
+                <h1>"Roles"</h1>
+                <p>
+                    "The broadcaster is always the owner. Twitch moderators without a role here are moderators, anyone else can't use the control page."
+                </p>
+                <ul style="margin: 0; padding: 0;">
+                    <For
+                        each=move || roles().into_iter()
+                        key=|(login, role)| (login.clone(), *role)
+                        children=move |(login, role): (String, Role)| {
+                                let removed_login = login.clone();
+                                let remove = move |_| set_role.run((removed_login.clone(), None));
+                                view! {
+                                    <li style="display: flex; align-items: center; gap: 0.5rem; list-style: none; margin: 0.25rem 0;">
+                                        <span style="flex-grow: 1; text-align: left;">
+                                            {login.clone()}
+                                        </span>
+                                        <RoleSelect
+                                            role
+                                            on_change=move |role| set_role.run((login.clone(), Some(role)))
+                                        />
+                                        <button on:click=remove title="Remove role">
+                                            "🗑"
+                                        </button>
+                                    </li>
+                                }
+                        }
+                    />
+                </ul>
+                <div style="display: flex; gap: 0.5rem; margin-top: 1rem;">
+                    <input
+                        placeholder="Twitch login"
+                        style="flex-grow: 1;"
+                        on:input=move |event| set_login(event_target_value(&event))
+                        prop:value=login
+                    />
+                    <RoleSelect role=Role::Editor on_change=move |role| set_new_role(role)/>
+                    <button on:click=assign>"Assign"</button>
+                </div>
+                <p>
+                    <a href="/control">"Back to the control page"</a>
+                </p>
+            </div>
+        </Show>
+    }
+}
+
+#[component]
+fn RoleSelect(role: Role, on_change: impl Fn(Role) + 'static) -> impl IntoView {
+    view! {
+        <select on:change=move |event| {
+            let index = event_target_value(&event).parse::<usize>().unwrap_or(0);
+            on_change(Role::ALL[index.min(Role::ALL.len() - 1)]);
+        }>
+            {Role::ALL
+                .iter()
+                .enumerate()
+                .map(|(index, option)| {
+                    view! {
+                        <option value=index selected=role == *option>
+                            {format!("{option:?}")}
+                        </option>
+                    }
+                })
+                .collect_view()}
+        </select>
+    }
+}
@@ -0,0 +1,105 @@
+//! Locally issued api tokens, letting bots and overlays in without a twitch account
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ApiToken, Scope,
+    hashing::{hash, random_hex},
+    json_file,
+};
+
+/// Where the tokens are stored, only hashes of the secrets are written
+pub const TOKENS_FILE: &str = "tokens.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    secret_hash: String,
+}
+
+#[derive(Debug, Default)]
+pub struct TokenStore {
+    tokens: IndexMap<String, StoredToken>,
+}
+
+impl TokenStore {
+    /// Loads the tokens from [`TOKENS_FILE`], starting empty if there is none
+    pub async fn load() -> Self {
+        Self {
+            tokens: json_file::load(TOKENS_FILE, "api tokens").await,
+        }
+    }
+
+    async fn save(&self) -> std::io::Result<()> {
+        json_file::save(TOKENS_FILE, &self.tokens).await
+    }
+
+    pub fn tokens(&self) -> Vec<ApiToken> {
+        self.tokens
+            .values()
+            .map(|stored| stored.token.clone())
+            .collect()
+    }
+
+    /// Creates a token, returning it with its secret
+    pub async fn create(
+        &mut self,
+        name: String,
+        scope: Scope,
+    ) -> std::io::Result<(ApiToken, String)> {
+        let id = random_hex(8);
+        let secret = format!("so_{}", random_hex(32));
+        let token = ApiToken {
+            id: id.clone(),
+            name,
+            scope,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        self.tokens.insert(
+            id,
+            StoredToken {
+                token: token.clone(),
+                secret_hash: hash(secret.as_bytes()),
+            },
+        );
+        self.save().await?;
+
+        Ok((token, secret))
+    }
+
+    /// Returns whether the token existed
+    pub async fn revoke(&mut self, id: &str) -> std::io::Result<bool> {
+        if self.tokens.shift_remove(id).is_none() {
+            return Ok(false);
+        }
+        self.save().await?;
+
+        Ok(true)
+    }
+
+    /// The scope of the token with this secret, `None` if there's no such token
+    pub fn scope_of(&self, secret: &str) -> Option<Scope> {
+        let secret_hash = hash(secret.as_bytes());
+        self.tokens
+            .values()
+            .find(|stored| stored.secret_hash == secret_hash)
+            .map(|stored| stored.token.scope)
+    }
+
+    /// The name of the token with this secret, `None` if there's no such token
+    pub fn name_of(&self, secret: &str) -> Option<String> {
+        let secret_hash = hash(secret.as_bytes());
+        self.tokens
+            .values()
+            .find(|stored| stored.secret_hash == secret_hash)
+            .map(|stored| stored.token.name.clone())
+    }
+}